@@ -52,6 +52,34 @@ export interface WasmWorldSnapshot {
   timestamp: number;
 }
 
+export interface TimingSummary {
+  samples: number;
+  min_ms: number;
+  avg_ms: number;
+  max_ms: number;
+  p99_ms: number;
+}
+
+export interface SystemProfileEntry extends TimingSummary {
+  system_id: string;
+  phase: string;
+  calls: number;
+  failures: number;
+  skips: number;
+  last_ms: number;
+}
+
+export interface PhaseProfileEntry extends TimingSummary {
+  phase: string;
+  calls: number;
+  last_ms: number;
+}
+
+export interface ProfileReport {
+  systems: SystemProfileEntry[];
+  phases: PhaseProfileEntry[];
+}
+
 export interface WasmWorldInterface {
   createEntity(): WasmEntity;
   createEntityWithId(id: number): WasmEntity;
@@ -67,6 +95,10 @@ export interface WasmWorldInterface {
   restoreFromSnapshot(snapshot: WasmWorldSnapshot): void;
   clear(): void;
   query(includeComponents: string[], excludeComponents: string[]): number[];
+  runFrame(realDt: number): void;
+  getSystemStats(): ProfileReport;
+  getSystemStatsJson(): string;
+  resetSystemStats(): void;
 }
 
 export declare class WasmWorld implements WasmWorldInterface {
@@ -85,6 +117,10 @@ export declare class WasmWorld implements WasmWorldInterface {
   restoreFromSnapshot(snapshot: WasmWorldSnapshot): void;
   clear(): void;
   query(includeComponents: string[], excludeComponents: string[]): number[];
+  runFrame(realDt: number): void;
+  getSystemStats(): ProfileReport;
+  getSystemStatsJson(): string;
+  resetSystemStats(): void;
   free(): void;
 }
 
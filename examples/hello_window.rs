@@ -1,5 +1,6 @@
 use tx2_core::App;
 
 fn main() {
-    pollster::block_on(App::run());
+    let app = pollster::block_on(App::new());
+    app.run();
 }
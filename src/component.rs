@@ -1,7 +1,12 @@
 use std::any::Any;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::encoding::{self, BinaryFormat};
+use crate::quantize::{self, Quantization};
 use crate::entity::EntityId;
 use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tx2_link::protocol::ComponentData;
 
 pub type ComponentId = String;
 
@@ -11,6 +16,15 @@ pub trait Component: Any + Send + Sync {
     fn component_id(&self) -> ComponentId;
     fn clone_box(&self) -> Box<dyn Component>;
     fn to_json(&self) -> serde_json::Value;
+
+    // Encodes the component directly in the given format. The default goes
+    // through `to_json`, which bincode cannot read back, so it is refused.
+    fn encode(&self, format: BinaryFormat) -> Result<Vec<u8>, String> {
+        if !encoding::is_self_describing(format) {
+            return Err(format!("{} has no {} encoding", self.component_id(), encoding::format_name(format)));
+        }
+        encoding::encode(&self.to_json(), format)
+    }
 }
 
 impl<T> Component for T
@@ -34,7 +48,410 @@ where
     }
 
     fn to_json(&self) -> serde_json::Value {
-        serde_json::to_value(self).unwrap_or(serde_json::Value::Null)
+        serde_json::to_value(self).unwrap_or_else(|e| {
+            log::error!("Failed to serialize component {}: {}", std::any::type_name::<T>(), e);
+            serde_json::Value::Null
+        })
+    }
+
+    fn encode(&self, format: BinaryFormat) -> Result<Vec<u8>, String> {
+        encoding::encode(self, format)
+    }
+}
+
+// Component whose Rust type is unknown to this process; carries its JSON
+// payload so it round-trips through snapshots unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicComponent {
+    pub id: ComponentId,
+    pub data: Value,
+}
+
+impl Component for DynamicComponent {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn component_id(&self) -> ComponentId {
+        self.id.clone()
+    }
+
+    fn clone_box(&self) -> Box<dyn Component> {
+        Box::new(self.clone())
+    }
+
+    fn to_json(&self) -> Value {
+        self.data.clone()
+    }
+}
+
+pub type DeserializeFn = fn(Value) -> Result<Box<dyn Component>, String>;
+pub type DecodeFn = fn(&[u8], BinaryFormat) -> Result<Box<dyn Component>, String>;
+// Upgrades component data by exactly one version.
+pub type MigrationFn = fn(Value) -> Result<Value, String>;
+
+fn decode_bytes<T: Component + DeserializeOwned>(bytes: &[u8], format: BinaryFormat) -> Result<Box<dyn Component>, String> {
+    encoding::decode::<T>(bytes, format).map(|component| Box::new(component) as Box<dyn Component>)
+}
+
+// JSON form of serialized component data, for migrations and dynamic components.
+pub fn component_data_json(data: &ComponentData, format: BinaryFormat) -> Result<Value, String> {
+    match data {
+        ComponentData::Json(json) => serde_json::from_str(json).map_err(|e| e.to_string()),
+        ComponentData::Binary(bytes) => encoding::decode_value(bytes, format),
+        ComponentData::Structured(_) => Err("structured component data is not supported".to_string()),
+    }
+}
+
+fn default_component<T: Component + Default>() -> Box<dyn Component> {
+    Box::new(T::default())
+}
+
+pub type DefaultFn = fn() -> Box<dyn Component>;
+
+// Recreates a transient component after a restore, on every restored entity
+// that carries `trigger` (or on every restored entity when there is none).
+#[derive(Debug, Clone)]
+pub struct TransientRebuild {
+    pub trigger: Option<ComponentId>,
+    build: DefaultFn,
+}
+
+fn deserialize_json<T: Component + DeserializeOwned>(value: Value) -> Result<Box<dyn Component>, String> {
+    serde_json::from_value::<T>(value)
+        .map(|component| Box::new(component) as Box<dyn Component>)
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub struct ComponentRegistration {
+    pub id: ComponentId,
+    pub type_name: &'static str,
+    pub version: u32,
+    deserialize: DeserializeFn,
+    decode: DecodeFn,
+    migrations: BTreeMap<u32, MigrationFn>,
+    quantization: Vec<(String, Quantization)>,
+}
+
+impl ComponentRegistration {
+    pub fn deserialize(&self, value: Value) -> Result<Box<dyn Component>, String> {
+        (self.deserialize)(value)
+    }
+
+    pub fn with_version(&mut self, version: u32) -> &mut Self {
+        assert!(version >= 1, "Component versions start at 1");
+        self.version = version;
+        self
+    }
+
+    // Registers the step that upgrades data written at `from_version` to `from_version + 1`.
+    pub fn with_migration(&mut self, from_version: u32, migration: MigrationFn) -> &mut Self {
+        self.migrations.insert(from_version, migration);
+        self
+    }
+
+    // Runs the migration chain from `from_version` up to the current version.
+    pub fn migrate(&self, mut value: Value, from_version: u32) -> Result<Value, String> {
+        if from_version > self.version {
+            return Err(format!(
+                "data version {} is newer than supported version {}",
+                from_version, self.version
+            ));
+        }
+        for version in from_version..self.version {
+            let migration = self.migrations.get(&version)
+                .ok_or_else(|| format!("no migration from version {} to {}", version, version + 1))?;
+            value = migration(value).map_err(|e| format!("migration from version {} failed: {}", version, e))?;
+        }
+        Ok(value)
+    }
+
+    // Quantizes the floats at `pointer` (a JSON pointer into the component's
+    // JSON form, see `quantize::quantize_at`) in deltas, and in snapshots
+    // taken with `SnapshotOptions::with_quantization`. The pointer must reach
+    // only float fields.
+    pub fn with_quantization(&mut self, pointer: &str, quantization: Quantization) -> &mut Self {
+        assert!(
+            pointer.is_empty() || pointer.starts_with('/'),
+            "Quantized field {} of {} must be a JSON pointer", pointer, self.id
+        );
+        self.quantization.retain(|(existing, _)| existing != pointer);
+        self.quantization.push((pointer.to_string(), quantization));
+        self
+    }
+
+    pub fn quantization(&self) -> &[(String, Quantization)] {
+        &self.quantization
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        !self.quantization.is_empty()
+    }
+
+    // A pointer that reaches an integer field is a registration mistake.
+    pub fn quantize(&self, value: &mut Value) {
+        for (pointer, quantization) in &self.quantization {
+            if let Err(e) = quantize::quantize_at(value, pointer, *quantization) {
+                panic!("Quantized field {} of {}: {}", pointer, self.id, e);
+            }
+        }
+    }
+
+    pub fn dequantize(&self, value: &mut Value) -> Result<(), String> {
+        for (pointer, quantization) in &self.quantization {
+            quantize::dequantize_at(value, pointer, *quantization)?;
+        }
+        Ok(())
+    }
+
+    pub fn deserialize_from(&self, value: Value, from_version: u32) -> Result<Box<dyn Component>, String> {
+        self.deserialize(self.migrate(value, from_version)?)
+    }
+
+    // Current-version binary data is decoded straight into the Rust type;
+    // anything that needs migrating or dequantizing goes through its JSON
+    // form. Bincode data is never quantized.
+    pub fn decode(&self, data: &ComponentData, format: BinaryFormat, from_version: u32) -> Result<Box<dyn Component>, String> {
+        let typed = !self.is_quantized() || format == BinaryFormat::Bincode;
+        match data {
+            ComponentData::Binary(bytes) if from_version == self.version && typed => (self.decode)(bytes, format),
+            _ => {
+                let mut value = component_data_json(data, format)?;
+                self.dequantize(&mut value)?;
+                self.deserialize_from(value, from_version)
+            }
+        }
+    }
+}
+
+// What a restore does with component ids that have no registration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownComponentPolicy {
+    #[default]
+    Error,
+    Skip,
+    KeepDynamic,
+}
+
+// Maps component ids to the typed deserializers used when restoring snapshots.
+#[derive(Debug, Default)]
+pub struct ComponentRegistry {
+    entries: HashMap<ComponentId, ComponentRegistration>,
+    unknown_policy: UnknownComponentPolicy,
+    // Component versions the data source announced (by a restore or a
+    // replicated full state); deltas from that source are migrated from
+    // these versions. Until a source announces, data is assumed current.
+    source_versions: Option<BTreeMap<ComponentId, u32>>,
+    source_format: Option<BinaryFormat>,
+    // Components left out of snapshots, deltas and save files.
+    transient: HashSet<ComponentId>,
+    rebuilds: BTreeMap<ComponentId, TransientRebuild>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registering the same type again returns the existing entry.
+    pub fn register<T: Component + DeserializeOwned>(&mut self) -> &mut ComponentRegistration {
+        let id = std::any::type_name::<T>().to_string();
+        self.entries.entry(id.clone()).or_insert_with(|| ComponentRegistration {
+            id,
+            type_name: std::any::type_name::<T>(),
+            version: 1,
+            deserialize: deserialize_json::<T>,
+            decode: decode_bytes::<T>,
+            migrations: BTreeMap::new(),
+            quantization: Vec::new(),
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ComponentRegistration> {
+        self.entries.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut ComponentRegistration> {
+        self.entries.get_mut(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    pub fn ids(&self) -> Vec<&ComponentId> {
+        let mut ids: Vec<&ComponentId> = self.entries.keys().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn versions(&self) -> BTreeMap<ComponentId, u32> {
+        self.entries.values().map(|r| (r.id.clone(), r.version)).collect()
+    }
+
+    // Version the current data source wrote `id` with. Components missing from
+    // an announced set are unversioned (version 1); with no announcement the
+    // source is assumed to run the registered version.
+    pub fn source_version(&self, id: &str) -> u32 {
+        match &self.source_versions {
+            Some(versions) => versions.get(id).copied().unwrap_or(1),
+            None => self.entries.get(id).map_or(1, |r| r.version),
+        }
+    }
+
+    pub fn set_source_versions(&mut self, versions: BTreeMap<ComponentId, u32>) {
+        self.source_versions = Some(versions);
+    }
+
+    // Encoding of binary component data from the current data source.
+    pub fn source_format(&self) -> BinaryFormat {
+        self.source_format.unwrap_or(BinaryFormat::Json)
+    }
+
+    pub fn set_source_format(&mut self, format: BinaryFormat) {
+        self.source_format = Some(format);
+    }
+
+    // Stable fingerprint of the registered component set (FNV-1a over the
+    // sorted ids, versions and quantization), so peers can tell whether their schemas agree.
+    pub fn schema_version(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for (id, version) in self.versions() {
+            let mut key = format!("{}@{}", id, version);
+            for (pointer, quantization) in self.entries[&id].quantization() {
+                key.push_str(&format!(";{}={:?}", pointer, quantization));
+            }
+            for byte in key.bytes().chain(std::iter::once(0)) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+
+    pub fn set_transient(&mut self, id: impl Into<ComponentId>) {
+        self.transient.insert(id.into());
+    }
+
+    pub fn is_transient(&self, id: &str) -> bool {
+        self.transient.contains(id)
+    }
+
+    pub fn transient_ids(&self) -> Vec<&ComponentId> {
+        let mut ids: Vec<&ComponentId> = self.transient.iter().collect();
+        ids.sort();
+        ids
+    }
+
+    // Marks `T` transient and rebuilds it from `T::default()` on restore.
+    pub fn rebuild_with_default<T: Component + Default>(&mut self, trigger: Option<ComponentId>) {
+        let id = std::any::type_name::<T>().to_string();
+        self.transient.insert(id.clone());
+        self.rebuilds.insert(id, TransientRebuild {
+            trigger,
+            build: default_component::<T>,
+        });
+    }
+
+    // Default transient components for an entity restored with `present`.
+    pub fn rebuild_defaults(&self, present: &HashSet<ComponentId>) -> Vec<Box<dyn Component>> {
+        self.rebuilds.iter()
+            .filter(|(id, rebuild)| {
+                !present.contains(*id)
+                    && rebuild.trigger.as_ref().is_none_or(|trigger| present.contains(trigger))
+            })
+            .map(|(_, rebuild)| (rebuild.build)())
+            .collect()
+    }
+
+    pub fn unknown_policy(&self) -> UnknownComponentPolicy {
+        self.unknown_policy
+    }
+
+    pub fn set_unknown_policy(&mut self, policy: UnknownComponentPolicy) {
+        self.unknown_policy = policy;
+    }
+}
+
+// Net changes since the last `take_changes`. Changes that cancel out within
+// the window (a component added then removed, an entity spawned then
+// despawned) are dropped rather than recorded twice.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeSet {
+    pub spawned: BTreeSet<EntityId>,
+    pub despawned: BTreeSet<EntityId>,
+    pub added: BTreeSet<(EntityId, ComponentId)>,
+    pub changed: BTreeSet<(EntityId, ComponentId)>,
+    pub removed: BTreeSet<(EntityId, ComponentId)>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty()
+            && self.despawned.is_empty()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+    }
+
+    pub fn record_spawned(&mut self, entity_id: EntityId) {
+        self.spawned.insert(entity_id);
+    }
+
+    pub fn record_despawned(&mut self, entity_id: EntityId) {
+        self.added.retain(|(e, _)| *e != entity_id);
+        self.changed.retain(|(e, _)| *e != entity_id);
+        self.removed.retain(|(e, _)| *e != entity_id);
+        if !self.spawned.remove(&entity_id) {
+            self.despawned.insert(entity_id);
+        }
+    }
+
+    pub fn record_added(&mut self, entity_id: EntityId, component_id: &str) {
+        let key = (entity_id, component_id.to_string());
+        // Removed and added back within the window: the receiver still has it.
+        if self.removed.remove(&key) {
+            self.changed.insert(key);
+        } else {
+            self.added.insert(key);
+        }
+    }
+
+    pub fn record_changed(&mut self, entity_id: EntityId, component_id: &str) {
+        let key = (entity_id, component_id.to_string());
+        if !self.added.contains(&key) {
+            self.changed.insert(key);
+        }
+    }
+
+    pub fn record_removed(&mut self, entity_id: EntityId, component_id: &str) {
+        let key = (entity_id, component_id.to_string());
+        self.changed.remove(&key);
+        if !self.added.remove(&key) {
+            self.removed.insert(key);
+        }
+    }
+}
+
+// One consumer's view of a store's change tracking. Each tracker has its own
+// change set, so draining one leaves the others intact. Ids are unique
+// across worlds, so a tracker never reads another world's changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChangeTracker(u64);
+
+static NEXT_CHANGE_TRACKER: AtomicU64 = AtomicU64::new(1);
+
+impl ChangeTracker {
+    // Backs `enable_change_tracking` and `take_changes`.
+    pub const DEFAULT: ChangeTracker = ChangeTracker(0);
+
+    fn next() -> Self {
+        ChangeTracker(NEXT_CHANGE_TRACKER.fetch_add(1, Ordering::Relaxed))
     }
 }
 
@@ -43,6 +460,8 @@ pub struct ComponentStore {
     components: HashMap<EntityId, HashMap<ComponentId, Vec<Box<dyn Component>>>>,
     // Map<ComponentId, Set<EntityId>>
     component_index: HashMap<ComponentId, HashSet<EntityId>>,
+    // One change set per tracker; nothing is recorded while there are none.
+    changes: BTreeMap<ChangeTracker, ChangeSet>,
 }
 
 impl ComponentStore {
@@ -50,15 +469,27 @@ impl ComponentStore {
         Self {
             components: HashMap::new(),
             component_index: HashMap::new(),
+            changes: BTreeMap::new(),
         }
     }
 
+    // An entity holds one component per type: adding another replaces it, as
+    // snapshots, deltas and change tracking all key components by type.
     pub fn add(&mut self, entity_id: EntityId, component: Box<dyn Component>) {
         let component_id = component.component_id();
         
         let entity_components = self.components.entry(entity_id).or_insert_with(HashMap::new);
         let list = entity_components.entry(component_id.clone()).or_insert_with(Vec::new);
+        let existed = !list.is_empty();
+        list.clear();
         list.push(component);
+        for changes in self.changes.values_mut() {
+            if existed {
+                changes.record_changed(entity_id, &component_id);
+            } else {
+                changes.record_added(entity_id, &component_id);
+            }
+        }
 
         let index = self.component_index.entry(component_id).or_insert_with(HashSet::new);
         index.insert(entity_id);
@@ -73,6 +504,9 @@ impl ComponentStore {
                         self.component_index.remove(component_id);
                     }
                 }
+                for changes in self.changes.values_mut() {
+                    changes.record_removed(entity_id, component_id);
+                }
                 return true;
             }
         }
@@ -90,6 +524,19 @@ impl ComponentStore {
         }
         None
     }
+
+    pub fn get_mut<T: Component>(&mut self, entity_id: EntityId) -> Option<&mut T> {
+        let component_id = std::any::type_name::<T>();
+        let component = self.components.get_mut(&entity_id)?
+            .get_mut(component_id)?
+            .first_mut()?
+            .as_any_mut()
+            .downcast_mut::<T>()?;
+        for changes in self.changes.values_mut() {
+            changes.record_changed(entity_id, component_id);
+        }
+        Some(component)
+    }
     
     pub fn get_all_by_type<T: Component>(&self, entity_id: EntityId) -> Vec<&T> {
         let component_id = std::any::type_name::<T>().to_string();
@@ -140,11 +587,81 @@ impl ComponentStore {
         }
     }
 
+    pub(crate) fn entity_components(&self, entity_id: EntityId) -> Option<&HashMap<ComponentId, Vec<Box<dyn Component>>>> {
+        self.components.get(&entity_id)
+    }
+
+    // Detaches an entity's components without touching the index; the caller
+    // must hand the same components back through `restore_entity_components`.
+    pub(crate) fn take_entity_components(&mut self, entity_id: EntityId) -> Option<HashMap<ComponentId, Vec<Box<dyn Component>>>> {
+        self.components.remove(&entity_id)
+    }
+
+    pub(crate) fn restore_entity_components(&mut self, entity_id: EntityId, components: HashMap<ComponentId, Vec<Box<dyn Component>>>) {
+        self.components.insert(entity_id, components);
+    }
+
     pub fn clear(&mut self) {
         self.components.clear();
         self.component_index.clear();
     }
 
+    pub fn enable_change_tracking(&mut self) {
+        self.changes.entry(ChangeTracker::DEFAULT).or_default();
+    }
+
+    pub fn disable_change_tracking(&mut self) {
+        self.changes.remove(&ChangeTracker::DEFAULT);
+    }
+
+    pub fn is_tracking_changes(&self) -> bool {
+        self.changes.contains_key(&ChangeTracker::DEFAULT)
+    }
+
+    pub fn changes(&self) -> Option<&ChangeSet> {
+        self.changes.get(&ChangeTracker::DEFAULT)
+    }
+
+    pub fn take_changes(&mut self) -> ChangeSet {
+        self.take_changes_for(ChangeTracker::DEFAULT).unwrap_or_default()
+    }
+
+    // Starts a change set of its own for a new consumer, recording from now on.
+    pub fn track_changes(&mut self) -> ChangeTracker {
+        let tracker = ChangeTracker::next();
+        self.changes.insert(tracker, ChangeSet::default());
+        tracker
+    }
+
+    pub fn untrack_changes(&mut self, tracker: ChangeTracker) {
+        self.changes.remove(&tracker);
+    }
+
+    // `None` if the tracker does not belong to this store.
+    pub fn take_changes_for(&mut self, tracker: ChangeTracker) -> Option<ChangeSet> {
+        self.changes.get_mut(&tracker).map(std::mem::take)
+    }
+
+    // For writes the store cannot see, such as components mutated in place
+    // through a system query.
+    pub fn mark_changed(&mut self, entity_id: EntityId, component_id: &str) {
+        for changes in self.changes.values_mut() {
+            changes.record_changed(entity_id, component_id);
+        }
+    }
+
+    pub(crate) fn record_spawned(&mut self, entity_id: EntityId) {
+        for changes in self.changes.values_mut() {
+            changes.record_spawned(entity_id);
+        }
+    }
+
+    pub(crate) fn record_despawned(&mut self, entity_id: EntityId) {
+        for changes in self.changes.values_mut() {
+            changes.record_despawned(entity_id);
+        }
+    }
+
     pub fn get_all_entities(&self) -> HashSet<EntityId> {
         self.components.keys().cloned().collect()
     }
@@ -0,0 +1,64 @@
+use std::any::Any;
+use std::ops::Not;
+use crate::state::{State, States};
+use crate::world::World;
+
+// A predicate evaluated against the world before a system runs.
+// Conditions compose with `and`, `or` and `!`.
+pub struct RunCondition {
+    predicate: Box<dyn Fn(&World) -> bool + Send + Sync>,
+}
+
+impl RunCondition {
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(&World) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            predicate: Box::new(predicate),
+        }
+    }
+
+    pub fn evaluate(&self, world: &World) -> bool {
+        (self.predicate)(world)
+    }
+
+    pub fn and(self, other: RunCondition) -> Self {
+        Self::new(move |world| self.evaluate(world) && other.evaluate(world))
+    }
+
+    pub fn or(self, other: RunCondition) -> Self {
+        Self::new(move |world| self.evaluate(world) || other.evaluate(world))
+    }
+}
+
+impl Not for RunCondition {
+    type Output = RunCondition;
+
+    fn not(self) -> Self::Output {
+        RunCondition::new(move |world| !self.evaluate(world))
+    }
+}
+
+pub fn resource_exists<R: Any + Send + Sync>() -> RunCondition {
+    RunCondition::new(|world| world.has_resource::<R>())
+}
+
+pub fn resource_equals<R>(value: R) -> RunCondition
+where
+    R: Any + Send + Sync + PartialEq,
+{
+    RunCondition::new(move |world| world.get_resource::<R>() == Some(&value))
+}
+
+pub fn resource_matches<R, F>(predicate: F) -> RunCondition
+where
+    R: Any + Send + Sync,
+    F: Fn(&R) -> bool + Send + Sync + 'static,
+{
+    RunCondition::new(move |world| world.get_resource::<R>().is_some_and(&predicate))
+}
+
+pub fn in_state<S: States>(state: S) -> RunCondition {
+    RunCondition::new(move |world| world.get_resource::<State<S>>().is_some_and(|s| *s.get() == state))
+}
@@ -0,0 +1,50 @@
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+pub use tx2_link::BinaryFormat;
+
+pub fn format_name(format: BinaryFormat) -> &'static str {
+    match format {
+        BinaryFormat::Json => "json",
+        BinaryFormat::MessagePack => "messagepack",
+        BinaryFormat::Bincode => "bincode",
+    }
+}
+
+pub fn parse_format(name: &str) -> Option<BinaryFormat> {
+    match name {
+        "json" => Some(BinaryFormat::Json),
+        "messagepack" => Some(BinaryFormat::MessagePack),
+        "bincode" => Some(BinaryFormat::Bincode),
+        _ => None,
+    }
+}
+
+// MessagePack keeps field names (`to_vec_named`), so like JSON it can be
+// read back without the Rust type; bincode cannot.
+pub fn is_self_describing(format: BinaryFormat) -> bool {
+    !matches!(format, BinaryFormat::Bincode)
+}
+
+pub fn encode<T: Serialize + ?Sized>(value: &T, format: BinaryFormat) -> Result<Vec<u8>, String> {
+    match format {
+        BinaryFormat::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        BinaryFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        BinaryFormat::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], format: BinaryFormat) -> Result<T, String> {
+    match format {
+        BinaryFormat::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+        BinaryFormat::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        BinaryFormat::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn decode_value(bytes: &[u8], format: BinaryFormat) -> Result<Value, String> {
+    if !is_self_describing(format) {
+        return Err(format!("{} data cannot be read without its component type", format_name(format)));
+    }
+    decode(bytes, format)
+}
@@ -13,6 +13,15 @@ pub fn create_entity_id() -> EntityId {
     }
 }
 
+// Ensures ids handed out later never collide with `id`, e.g. after a restore.
+pub fn advance_entity_id_counter(id: EntityId) {
+    unsafe {
+        if NEXT_ENTITY_ID <= id {
+            NEXT_ENTITY_ID = id + 1;
+        }
+    }
+}
+
 pub fn reset_entity_id_counter(start: u32) {
     unsafe {
         NEXT_ENTITY_ID = start;
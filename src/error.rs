@@ -1,5 +1,7 @@
 use thiserror::Error;
-use crate::system::SystemId;
+use crate::component::ComponentId;
+use crate::entity::EntityId;
+use crate::system::{SystemId, SystemPhase};
 
 #[derive(Error, Debug)]
 pub enum TX2Error {
@@ -8,6 +10,49 @@ pub enum TX2Error {
         message: String,
         code: String,
     },
+
+    #[error("Circular system dependency in phase {phase}: {}", cycle.join(" -> "))]
+    SystemCycle {
+        phase: SystemPhase,
+        cycle: Vec<SystemId>,
+    },
+
+    #[error("Phase {phase} is not registered with the scheduler")]
+    UnknownPhase {
+        phase: String,
+    },
+
+    #[error("Unknown component {component_id} on entity {entity_id}")]
+    UnknownComponent {
+        entity_id: EntityId,
+        component_id: ComponentId,
+    },
+
+    #[error("Incompatible snapshot format {found}, expected {expected}")]
+    IncompatibleSnapshot {
+        expected: String,
+        found: String,
+    },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Corrupt world file: {message}")]
+    CorruptFile {
+        message: String,
+    },
+
+    #[error("Encoding failed: {message}")]
+    Encoding {
+        message: String,
+    },
+
+    #[error("Failed to deserialize component {component_id} on entity {entity_id}: {message}")]
+    ComponentDeserialize {
+        entity_id: EntityId,
+        component_id: ComponentId,
+        message: String,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,19 +62,24 @@ pub enum SystemErrorStrategy {
     Retry,
 }
 
+#[derive(Debug, Clone)]
 pub struct SystemErrorContext {
     pub system_id: SystemId,
     pub error: String, // Rust errors are traits, simplified to String for context
-    pub phase: String,
+    pub phase: SystemPhase,
     pub consecutive_failures: u32,
+    pub panicked: bool,
+    pub attempt: u32, // 0 for the first run in a frame, incremented on each retry
+    pub frame: u64,
+    pub elapsed_time: f64,
 }
 
 pub type SystemErrorHandler = fn(&SystemErrorContext) -> SystemErrorStrategy;
 
 pub fn default_error_handler(ctx: &SystemErrorContext) -> SystemErrorStrategy {
     eprintln!(
-        "System {} failed in phase {}: {} (failures: {})",
-        ctx.system_id, ctx.phase, ctx.error, ctx.consecutive_failures
+        "System {} failed in phase {} at frame {}: {} (failures: {})",
+        ctx.system_id, ctx.phase, ctx.frame, ctx.error, ctx.consecutive_failures
     );
 
     if ctx.consecutive_failures >= 3 {
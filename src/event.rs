@@ -0,0 +1,65 @@
+use crate::world::World;
+
+// Double-buffered event queue stored as a world resource. Events stay
+// readable for two updates, so every system sees each event once regardless
+// of whether it runs before or after the sender within the frame.
+pub struct Events<E> {
+    current: Vec<(u64, E)>,
+    previous: Vec<(u64, E)>,
+    next_id: u64,
+}
+
+impl<E: Clone + Send + Sync + 'static> Events<E> {
+    pub fn new() -> Self {
+        Self {
+            current: Vec::new(),
+            previous: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn send(&mut self, event: E) {
+        self.current.push((self.next_id, event));
+        self.next_id += 1;
+    }
+
+    // Drops events older than the previous update.
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    // Events with an id at or past `cursor`, oldest first, and the new cursor.
+    pub fn read_from(&self, cursor: u64) -> (Vec<E>, u64) {
+        let events = self.previous.iter()
+            .chain(self.current.iter())
+            .filter(|(id, _)| *id >= cursor)
+            .map(|(_, event)| event.clone())
+            .collect();
+        (events, self.next_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.previous.clear();
+    }
+}
+
+impl<E: Clone + Send + Sync + 'static> Default for Events<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn update_events<E: Clone + Send + Sync + 'static>(world: &mut World) {
+    if let Some(events) = world.get_resource_mut::<Events<E>>() {
+        events.update();
+    }
+}
@@ -1,8 +1,18 @@
 pub mod component;
 pub mod entity;
 pub mod system;
+pub mod system_param;
+pub mod event;
+pub mod condition;
+pub mod state;
+pub mod profiling;
 pub mod world;
 pub mod serialization;
+pub mod replication;
+pub mod encoding;
+pub mod patch;
+pub mod quantize;
+pub mod persistence;
 pub mod query;
 pub mod error;
 
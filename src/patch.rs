@@ -0,0 +1,167 @@
+use serde_json::{Map, Value};
+use tx2_link::protocol::{FieldDelta, FieldValue};
+
+// Field-level component diffs carried in `DeltaChange::FieldsUpdated`.
+//
+// A field id starting with "/" is a JSON pointer (RFC 6901) into the
+// component's JSON form; anything else is a top-level key, as produced by
+// tx2-link itself. A pointer ending in "/-" appends to the array it names.
+// Pointer patches carry no old values, so they apply unconditionally.
+
+pub fn is_pointer(field_id: &str) -> bool {
+    field_id.starts_with('/')
+}
+
+// The changes that turn `old` into `new`, or `None` when only a whole
+// replacement can express them (the root is not an object or lost keys).
+pub fn diff(old: &Value, new: &Value) -> Option<Vec<FieldDelta>> {
+    let (Value::Object(old_fields), Value::Object(new_fields)) = (old, new) else {
+        return None;
+    };
+    if old_fields.keys().any(|key| !new_fields.contains_key(key)) {
+        return None;
+    }
+    let mut fields = Vec::new();
+    diff_object(old_fields, new_fields, "", &mut fields);
+    Some(fields)
+}
+
+fn diff_value(old: &Value, new: &Value, path: String, fields: &mut Vec<FieldDelta>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields))
+            if old_fields.keys().all(|key| new_fields.contains_key(key)) =>
+        {
+            diff_object(old_fields, new_fields, &path, fields);
+        }
+        (Value::Array(old_items), Value::Array(new_items)) if new_items.len() >= old_items.len() => {
+            for (index, (old_item, new_item)) in old_items.iter().zip(new_items).enumerate() {
+                diff_value(old_item, new_item, format!("{}/{}", path, index), fields);
+            }
+            for item in &new_items[old_items.len()..] {
+                fields.push(set(format!("{}/-", path), item));
+            }
+        }
+        _ => fields.push(set(path, new)),
+    }
+}
+
+fn diff_object(old: &Map<String, Value>, new: &Map<String, Value>, path: &str, fields: &mut Vec<FieldDelta>) {
+    for (key, new_value) in new {
+        let child = format!("{}/{}", path, escape(key));
+        match old.get(key) {
+            Some(old_value) => diff_value(old_value, new_value, child, fields),
+            None => fields.push(set(child, new_value)),
+        }
+    }
+}
+
+fn set(pointer: String, value: &Value) -> FieldDelta {
+    FieldDelta {
+        field_id: pointer,
+        old_value: None,
+        new_value: json_to_field_value(value),
+    }
+}
+
+// Applies one pointer patch. Fails when the pointer's parent does not exist
+// or is not a container the last segment can address.
+pub fn apply(value: &mut Value, field: &FieldDelta) -> Result<(), String> {
+    let mut segments = pointer_segments(&field.field_id);
+    let last = segments.pop().unwrap_or_default();
+    let mut target = value;
+    for segment in &segments {
+        target = match target {
+            Value::Object(fields) => fields.get_mut(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|index| items.get_mut(index)),
+            _ => None,
+        }
+        .ok_or_else(|| format!("{} does not exist", field.field_id))?;
+    }
+
+    let new_value = field_value_to_json(&field.new_value);
+    match target {
+        Value::Object(fields) => {
+            fields.insert(last, new_value);
+            Ok(())
+        }
+        Value::Array(items) if last == "-" => {
+            items.push(new_value);
+            Ok(())
+        }
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(index) if index < items.len() => {
+                items[index] = new_value;
+                Ok(())
+            }
+            Ok(index) if index == items.len() => {
+                items.push(new_value);
+                Ok(())
+            }
+            _ => Err(format!("{} is out of bounds", field.field_id)),
+        },
+        _ => Err(format!("{} does not address a container", field.field_id)),
+    }
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+// Unescaped segments of a pointer; the root pointer "" has none.
+pub(crate) fn pointer_segments(pointer: &str) -> Vec<String> {
+    match pointer.strip_prefix('/') {
+        Some(rest) => rest.split('/').map(unescape).collect(),
+        None => Vec::new(),
+    }
+}
+
+pub fn json_to_field_value(value: &Value) -> FieldValue {
+    match value {
+        Value::Null => FieldValue::Null,
+        Value::Bool(b) => FieldValue::Bool(*b),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                FieldValue::I64(i)
+            } else if let Some(u) = n.as_u64() {
+                FieldValue::U64(u)
+            } else {
+                n.as_f64().map_or(FieldValue::Null, FieldValue::F64)
+            }
+        }
+        Value::String(s) => FieldValue::String(s.clone()),
+        Value::Array(items) => FieldValue::Array(items.iter().map(json_to_field_value).collect()),
+        Value::Object(fields) => FieldValue::Map(
+            fields.iter().map(|(k, v)| (k.clone(), json_to_field_value(v))).collect(),
+        ),
+    }
+}
+
+pub fn field_value_to_json(value: &FieldValue) -> Value {
+    match value {
+        FieldValue::Null => Value::Null,
+        FieldValue::Bool(b) => Value::Bool(*b),
+        FieldValue::U8(n) => Value::from(*n),
+        FieldValue::U16(n) => Value::from(*n),
+        FieldValue::U32(n) => Value::from(*n),
+        FieldValue::U64(n) => Value::from(*n),
+        FieldValue::I8(n) => Value::from(*n),
+        FieldValue::I16(n) => Value::from(*n),
+        FieldValue::I32(n) => Value::from(*n),
+        FieldValue::I64(n) => Value::from(*n),
+        FieldValue::F32(n) => serde_json::Number::from_f64(*n as f64).map_or(Value::Null, Value::Number),
+        FieldValue::F64(n) => serde_json::Number::from_f64(*n).map_or(Value::Null, Value::Number),
+        FieldValue::String(s) => Value::String(s.clone()),
+        FieldValue::Bytes(bytes) => Value::Array(bytes.iter().map(|b| Value::from(*b)).collect()),
+        FieldValue::Array(items) => Value::Array(items.iter().map(field_value_to_json).collect()),
+        FieldValue::Map(map) => Value::Object(
+            map.iter().map(|(k, v)| (k.clone(), field_value_to_json(v))).collect(),
+        ),
+    }
+}
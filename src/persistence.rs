@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::encoding::{self, BinaryFormat};
+use crate::error::TX2Error;
+use crate::serialization::{Serializer, SnapshotOptions};
+use crate::world::World;
+use tx2_link::WorldSnapshot;
+
+// File layout, little-endian:
+//   magic "TX2W" | file version u16 | encoding u8 | flags u8 |
+//   payload length u64 | uncompressed length u64 | CRC-32 u32 | payload
+// The CRC-32 covers the header fields before it and the payload. Compressed
+// payloads are LZ4 frames.
+pub const MAGIC: &[u8; 4] = b"TX2W";
+pub const FILE_VERSION: u16 = 2;
+pub const HEADER_LEN: usize = 28;
+const CHECKSUM_OFFSET: usize = 24;
+const FLAG_COMPRESSED: u8 = 1;
+// LZ4 cannot expand data by more than this; larger claims are corrupt.
+const MAX_COMPRESSION_RATIO: u64 = 255;
+
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    pub snapshot: SnapshotOptions,
+    pub compress: bool,
+}
+
+impl SaveOptions {
+    pub fn new() -> Self {
+        Self {
+            snapshot: SnapshotOptions::new().with_format(BinaryFormat::MessagePack),
+            compress: true,
+        }
+    }
+
+    pub fn with_format(mut self, format: BinaryFormat) -> Self {
+        self.snapshot.format = format;
+        self
+    }
+
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.snapshot = self.snapshot.with_metadata(key, value);
+        self
+    }
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn format_tag(format: BinaryFormat) -> u8 {
+    match format {
+        BinaryFormat::Json => 0,
+        BinaryFormat::MessagePack => 1,
+        BinaryFormat::Bincode => 2,
+    }
+}
+
+fn format_from_tag(tag: u8) -> Option<BinaryFormat> {
+    match tag {
+        0 => Some(BinaryFormat::Json),
+        1 => Some(BinaryFormat::MessagePack),
+        2 => Some(BinaryFormat::Bincode),
+        _ => None,
+    }
+}
+
+fn corrupt(message: impl Into<String>) -> TX2Error {
+    TX2Error::CorruptFile { message: message.into() }
+}
+
+pub fn encode_file(snapshot: &WorldSnapshot, format: BinaryFormat, compress: bool) -> Result<Vec<u8>, TX2Error> {
+    let raw = encoding::encode(snapshot, format).map_err(|message| TX2Error::Encoding { message })?;
+    let payload = if compress { compress_bytes(&raw)? } else { raw.clone() };
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FILE_VERSION.to_le_bytes());
+    bytes.push(format_tag(format));
+    bytes.push(if compress { FLAG_COMPRESSED } else { 0 });
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(raw.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&checksum(&bytes, &payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn decode_file(bytes: &[u8]) -> Result<WorldSnapshot, TX2Error> {
+    if bytes.len() < HEADER_LEN {
+        return Err(corrupt(format!("file is truncated: {} bytes, header needs {}", bytes.len(), HEADER_LEN)));
+    }
+    if &bytes[0..4] != MAGIC {
+        return Err(corrupt("not a TX2 world file"));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FILE_VERSION {
+        return Err(TX2Error::IncompatibleSnapshot {
+            expected: format!("file version {}", FILE_VERSION),
+            found: format!("file version {}", version),
+        });
+    }
+    let format = format_from_tag(bytes[6]).ok_or_else(|| corrupt(format!("unknown encoding tag {}", bytes[6])))?;
+    let flags = bytes[7];
+    let payload_len = read_u64(&bytes[8..16]);
+    let raw_len = read_u64(&bytes[16..24]);
+    let stored_checksum = u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]);
+
+    let payload = &bytes[HEADER_LEN..];
+    if (payload.len() as u64) < payload_len {
+        return Err(corrupt(format!("file is truncated: payload has {} of {} bytes", payload.len(), payload_len)));
+    }
+    if (payload.len() as u64) > payload_len {
+        return Err(corrupt("unexpected data after payload"));
+    }
+    if checksum(&bytes[..CHECKSUM_OFFSET], payload) != stored_checksum {
+        return Err(corrupt("checksum mismatch"));
+    }
+
+    let compressed = flags & FLAG_COMPRESSED != 0;
+    let max_raw_len = if compressed { payload_len.saturating_mul(MAX_COMPRESSION_RATIO) } else { payload_len };
+    if raw_len > max_raw_len {
+        return Err(corrupt(format!("uncompressed length {} is impossible for a {} byte payload", raw_len, payload_len)));
+    }
+    let raw = if compressed {
+        decompress_bytes(payload, raw_len).ok_or_else(|| corrupt("compressed payload is malformed"))?
+    } else {
+        payload.to_vec()
+    };
+    encoding::decode(&raw, format).map_err(|message| corrupt(format!("payload does not decode: {}", message)))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[..CHECKSUM_OFFSET]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+pub fn compress_bytes(input: &[u8]) -> Result<Vec<u8>, TX2Error> {
+    let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+    encoder.write_all(input)?;
+    encoder.finish().map_err(|e| TX2Error::Encoding { message: e.to_string() })
+}
+
+// Grows the output as data decodes rather than trusting `expected_len`.
+pub fn decompress_bytes(input: &[u8], expected_len: u64) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    lz4_flex::frame::FrameDecoder::new(input)
+        .take(expected_len.saturating_add(1))
+        .read_to_end(&mut out)
+        .ok()?;
+    (out.len() as u64 == expected_len).then_some(out)
+}
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Writes to a sibling temp file and renames it over `path`, so readers never
+// observe a half-written file. Temp names are unique per process and call,
+// so concurrent saves to one path cannot clobber each other's temp file.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), TX2Error> {
+    let mut temp_name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    temp_name.push(format!(".{}.{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let temp_path: PathBuf = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        sync_parent(path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(TX2Error::from)
+}
+
+// Persists the rename itself. Directories cannot be opened for syncing on
+// every platform, so this is Unix only.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => fs::File::open(parent)?.sync_all(),
+        None => fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+pub fn save_world(world: &World, path: &Path, options: &SaveOptions) -> Result<(), TX2Error> {
+    let snapshot = Serializer::create_snapshot_with(world, &options.snapshot);
+    let bytes = encode_file(&snapshot, options.snapshot.format, options.compress)?;
+    write_atomic(path, &bytes)
+}
+
+pub fn read_snapshot(path: &Path) -> Result<WorldSnapshot, TX2Error> {
+    decode_file(&fs::read(path)?)
+}
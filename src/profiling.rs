@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+
+pub const DEFAULT_PROFILE_WINDOW: usize = 120;
+
+// Milliseconds from an arbitrary, monotonic origin.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    ORIGIN.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+// `Instant` is unavailable on wasm32-unknown-unknown; use the page clock instead.
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map(|p| p.now())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+// Rolling window over the most recent duration samples.
+#[derive(Debug, Clone)]
+pub struct TimingWindow {
+    samples: VecDeque<f64>,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TimingSummary {
+    pub samples: usize,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl TimingWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&mut self, duration_ms: f64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(duration_ms);
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn last(&self) -> Option<f64> {
+        self.samples.back().copied()
+    }
+
+    pub fn summary(&self) -> TimingSummary {
+        if self.samples.is_empty() {
+            return TimingSummary::default();
+        }
+
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let n = sorted.len();
+        let p99_index = ((n as f64 * 0.99).ceil() as usize).clamp(1, n) - 1;
+
+        TimingSummary {
+            samples: n,
+            min_ms: sorted[0],
+            avg_ms: sorted.iter().sum::<f64>() / n as f64,
+            max_ms: sorted[n - 1],
+            p99_ms: sorted[p99_index],
+        }
+    }
+}
+
+impl Default for TimingWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROFILE_WINDOW)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemProfileEntry {
+    pub system_id: String,
+    pub phase: String,
+    pub calls: u64,
+    pub failures: u64,
+    pub skips: u64,
+    pub last_ms: f64,
+    #[serde(flatten)]
+    pub timing: TimingSummary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseProfileEntry {
+    pub phase: String,
+    pub calls: u64,
+    pub last_ms: f64,
+    #[serde(flatten)]
+    pub timing: TimingSummary,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProfileReport {
+    pub systems: Vec<SystemProfileEntry>,
+    pub phases: Vec<PhaseProfileEntry>,
+}
+
+impl ProfileReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    // Systems whose worst recent run exceeded the given budget, slowest first.
+    pub fn over_budget(&self, budget_ms: f64) -> Vec<&SystemProfileEntry> {
+        let mut entries: Vec<&SystemProfileEntry> = self.systems.iter()
+            .filter(|e| e.timing.max_ms > budget_ms)
+            .collect();
+        entries.sort_by(|a, b| b.timing.max_ms.total_cmp(&a.timing.max_ms));
+        entries
+    }
+}
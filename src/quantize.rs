@@ -0,0 +1,163 @@
+use serde_json::Value;
+use crate::patch::pointer_segments;
+
+// Lossy numeric encodings for replicated fields. Quantized fields travel as
+// integers in the component's JSON form and are decoded back to floats on
+// restore and delta application.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantization {
+    // Multiples of `step`.
+    Fixed { step: f64 },
+    // `bits`-bit integer spread evenly over [min, max]; values outside are clamped.
+    Range { min: f64, max: f64, bits: u8 },
+    // IEEE 754 half precision, sent as its 16 bits.
+    Half,
+}
+
+impl Quantization {
+    pub fn fixed(step: f64) -> Self {
+        assert!(step > 0.0 && step.is_finite(), "Fixed quantization needs a positive step, got {}", step);
+        Quantization::Fixed { step }
+    }
+
+    pub fn range(min: f64, max: f64, bits: u8) -> Self {
+        assert!(min < max, "Range quantization needs min < max, got {}..{}", min, max);
+        assert!((1..=32).contains(&bits), "Range quantization needs 1 to 32 bits, got {}", bits);
+        Quantization::Range { min, max, bits }
+    }
+
+    pub fn quantize(&self, value: f64) -> Value {
+        match *self {
+            Quantization::Fixed { step } => Value::from((value / step).round() as i64),
+            Quantization::Range { min, max, bits } => {
+                let levels = ((1u64 << bits) - 1) as f64;
+                let t = (value.clamp(min, max) - min) / (max - min);
+                Value::from((t * levels).round() as u64)
+            }
+            Quantization::Half => Value::from(f32_to_f16_bits(value as f32)),
+        }
+    }
+
+    pub fn dequantize(&self, value: &Value) -> Option<f64> {
+        match *self {
+            Quantization::Fixed { step } => value.as_i64().map(|q| q as f64 * step),
+            Quantization::Range { min, max, bits } => {
+                let levels = ((1u64 << bits) - 1) as f64;
+                value.as_u64().filter(|q| *q as f64 <= levels).map(|q| min + q as f64 / levels * (max - min))
+            }
+            Quantization::Half => value.as_u64()
+                .and_then(|bits| u16::try_from(bits).ok())
+                .map(|bits| f16_bits_to_f32(bits) as f64),
+        }
+    }
+
+    // Largest difference between a value in range and its decoded form.
+    pub fn max_error(&self) -> f64 {
+        match *self {
+            Quantization::Fixed { step } => step / 2.0,
+            Quantization::Range { min, max, bits } => (max - min) / ((1u64 << bits) - 1) as f64 / 2.0,
+            // Relative: half the spacing of an 11-bit significand.
+            Quantization::Half => 1.0 / 2048.0,
+        }
+    }
+}
+
+// Quantizes the floats `pointer` addresses. A "*" segment matches every
+// element or value of a container, and a pointer ending at an array covers
+// its elements. Nulls and non-numbers are left alone. Integers are refused:
+// the receiver would take them for quantized values.
+pub fn quantize_at(value: &mut Value, pointer: &str, quantization: Quantization) -> Result<(), String> {
+    visit(value, &pointer_segments(pointer), &mut |leaf| {
+        if leaf.is_i64() || leaf.is_u64() {
+            return Err(format!("{} at {} is an integer, only floats can be quantized", leaf, pointer));
+        }
+        if let Some(number) = leaf.as_f64() {
+            *leaf = quantization.quantize(number);
+        }
+        Ok(())
+    })
+}
+
+// Quantized values are integers; floats are exact values that were never
+// quantized (a save file, say) and are left alone. `quantize_at` never lets
+// a real integer through, so every integer here is a quantized float.
+pub fn dequantize_at(value: &mut Value, pointer: &str, quantization: Quantization) -> Result<(), String> {
+    visit(value, &pointer_segments(pointer), &mut |leaf| {
+        if leaf.is_i64() || leaf.is_u64() {
+            let number = quantization.dequantize(leaf)
+                .ok_or_else(|| format!("{} at {} is not a quantized value", leaf, pointer))?;
+            *leaf = serde_json::Number::from_f64(number).map_or(Value::Null, Value::Number);
+        }
+        Ok(())
+    })
+}
+
+fn visit(value: &mut Value, segments: &[String], f: &mut dyn FnMut(&mut Value) -> Result<(), String>) -> Result<(), String> {
+    let Some((head, rest)) = segments.split_first() else {
+        return match value {
+            Value::Array(items) => items.iter_mut().try_for_each(f),
+            _ => f(value),
+        };
+    };
+    match value {
+        Value::Object(fields) if head == "*" => fields.values_mut().try_for_each(|v| visit(v, rest, f)),
+        Value::Array(items) if head == "*" => items.iter_mut().try_for_each(|v| visit(v, rest, f)),
+        Value::Object(fields) => fields.get_mut(head).map_or(Ok(()), |v| visit(v, rest, f)),
+        Value::Array(items) => head.parse::<usize>().ok()
+            .and_then(|index| items.get_mut(index))
+            .map_or(Ok(()), |v| visit(v, rest, f)),
+        _ => Ok(()),
+    }
+}
+
+// Round to nearest, ties to even; out-of-range values become infinity.
+pub fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half = full >> shift;
+        let remainder = full & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+    let half = sign | ((half_exponent as u16) << 10) | (mantissa >> 13) as u16;
+    let remainder = mantissa & 0x1fff;
+    if remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    }
+}
+
+pub fn f16_bits_to_f32(half: u16) -> f32 {
+    let negative = half & 0x8000 != 0;
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    match (exponent, mantissa) {
+        (0, 0) => f32::from_bits(sign),
+        (0, _) => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if negative { -magnitude } else { magnitude }
+        }
+        (0x1f, 0) => f32::from_bits(sign | 0x7f80_0000),
+        (0x1f, _) => f32::NAN,
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
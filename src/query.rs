@@ -9,7 +9,7 @@ pub enum QueryFilter {
     None(Vec<ComponentId>),
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
 pub struct QueryDescriptor {
     pub all: Vec<ComponentId>,
     pub any: Vec<ComponentId>,
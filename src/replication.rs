@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::ops::Deref;
+use crate::component::{ChangeSet, ChangeTracker, Component, ComponentId};
+use crate::encoding::BinaryFormat;
+use crate::entity::EntityId;
+use crate::serialization::{Baseline, DeltaApplyReport, SnapshotOptions};
+use crate::world::World;
+use tx2_link::{Delta, DeltaChange};
+
+pub type ClientId = u64;
+
+pub const DEFAULT_HISTORY: usize = 32;
+
+type RelevanceFn = dyn Fn(&World, EntityId) -> bool + Send + Sync;
+
+// Decides which entities a client should know about. Relevance composes
+// with `and` and `or`, like run conditions.
+pub struct Relevance {
+    predicate: Box<RelevanceFn>,
+}
+
+impl Relevance {
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(&World, EntityId) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            predicate: Box::new(predicate),
+        }
+    }
+
+    pub fn all() -> Self {
+        Self::new(|_, _| true)
+    }
+
+    // Only subscribed entities.
+    pub fn none() -> Self {
+        Self::new(|_, _| false)
+    }
+
+    pub fn with_component<T: Component>() -> Self {
+        let component_id = std::any::type_name::<T>();
+        Self::new(move |world, entity| world.has_component(entity, component_id))
+    }
+
+    // Entities whose `P` lies within `radius` of the viewer's `P`. The viewer
+    // itself is always relevant; nothing else is while the viewer has no `P`.
+    pub fn within_distance<P: Component>(viewer: EntityId, radius: f64, position: fn(&P) -> [f64; 3]) -> Self {
+        Self::new(move |world, entity| {
+            if entity == viewer {
+                return true;
+            }
+            let (Some(origin), Some(target)) = (world.get_component::<P>(viewer), world.get_component::<P>(entity)) else {
+                return false;
+            };
+            let (origin, target) = (position(origin), position(target));
+            let distance_squared: f64 = origin.iter().zip(&target).map(|(a, b)| (a - b) * (a - b)).sum();
+            distance_squared <= radius * radius
+        })
+    }
+
+    pub fn evaluate(&self, world: &World, entity: EntityId) -> bool {
+        (self.predicate)(world, entity)
+    }
+
+    pub fn and(self, other: Relevance) -> Self {
+        Self::new(move |world, entity| self.evaluate(world, entity) && other.evaluate(world, entity))
+    }
+
+    pub fn or(self, other: Relevance) -> Self {
+        Self::new(move |world, entity| self.evaluate(world, entity) || other.evaluate(world, entity))
+    }
+}
+
+// A client's delta, numbered per client. `base` is the sequence the
+// receiver must have applied first; `None` marks full state, which replaces
+// everything the receiver holds. `versions` are the sender's component
+// versions, so the receiver migrates only data that is actually older, and
+// `format` is how binary component data is encoded.
+#[derive(Debug, Clone)]
+pub struct SequencedDelta {
+    pub sequence: u64,
+    pub base: Option<u64>,
+    pub versions: BTreeMap<ComponentId, u32>,
+    pub format: BinaryFormat,
+    pub delta: Delta,
+}
+
+impl SequencedDelta {
+    pub fn is_full(&self) -> bool {
+        self.base.is_none()
+    }
+}
+
+impl Deref for SequencedDelta {
+    type Target = Delta;
+
+    fn deref(&self) -> &Delta {
+        &self.delta
+    }
+}
+
+// What one sent delta touched, kept until the client acknowledges it.
+struct Touched {
+    sequence: u64,
+    entities: BTreeSet<EntityId>,
+    components: BTreeSet<(EntityId, ComponentId)>,
+}
+
+impl Touched {
+    fn new(sequence: u64, changes: &[DeltaChange]) -> Self {
+        let mut touched = Self {
+            sequence,
+            entities: BTreeSet::new(),
+            components: BTreeSet::new(),
+        };
+        for change in changes {
+            match change {
+                DeltaChange::EntityAdded { entity_id } | DeltaChange::EntityRemoved { entity_id } => {
+                    touched.entities.insert(*entity_id);
+                }
+                DeltaChange::ComponentAdded { entity_id, component_id, .. }
+                | DeltaChange::ComponentUpdated { entity_id, component_id, .. }
+                | DeltaChange::ComponentRemoved { entity_id, component_id }
+                | DeltaChange::FieldsUpdated { entity_id, component_id, .. } => {
+                    touched.components.insert((*entity_id, component_id.clone()));
+                }
+            }
+        }
+        touched
+    }
+}
+
+struct ClientState {
+    relevance: Relevance,
+    subscriptions: HashSet<EntityId>,
+    baseline: Baseline,
+    base_timestamp: Option<f64>,
+    reliable: bool,
+    next_sequence: u64,
+    // First sequence of the current baseline; older acks say nothing about it.
+    epoch: u64,
+    acked: Option<u64>,
+    // Last full state sent, which deltas build on until something is acked.
+    full: Option<u64>,
+    unacked: VecDeque<Touched>,
+}
+
+impl ClientState {
+    fn new(relevance: Relevance, reliable: bool) -> Self {
+        Self {
+            relevance,
+            subscriptions: HashSet::new(),
+            baseline: Baseline::new(),
+            base_timestamp: None,
+            reliable,
+            next_sequence: 1,
+            epoch: 1,
+            acked: None,
+            full: None,
+            unacked: VecDeque::new(),
+        }
+    }
+
+    // The sequence a delta can build on: the last acknowledged one or, before
+    // any acknowledgement, the last full state, provided every delta sent
+    // since is still in the history. A client that acknowledges nothing
+    // within the history is therefore sent full state again.
+    fn delta_base(&self) -> Option<u64> {
+        let covered = |base: &u64| self.unacked.front().is_none_or(|oldest| oldest.sequence <= base + 1);
+        self.acked.filter(|acked| *acked >= self.epoch).filter(covered)
+            .or_else(|| self.full.filter(covered))
+    }
+}
+
+// One delta stream per client, each against the client's own baseline.
+// Entities that become relevant to a client are sent in full and entities
+// that stop being relevant are despawned on it. Relevance is re-evaluated
+// for every entity on every update, since it can change without the entity
+// changing (a viewer moving, for instance).
+//
+// Clients on unreliable transports acknowledge the sequences they apply.
+// Their deltas build on the last acknowledged state (before the first
+// acknowledgement, on the last full state) and restate, in full, everything
+// touched by deltas sent since, so they apply on top of any of those. When
+// that base falls out of the history the client gets full state again.
+// Reliable clients count as acknowledging every delta on send.
+pub struct Replicator {
+    options: SnapshotOptions,
+    history: usize,
+    clients: BTreeMap<ClientId, ClientState>,
+    tracker: Option<ChangeTracker>,
+}
+
+impl Replicator {
+    pub fn new() -> Self {
+        Self::with_options(SnapshotOptions::new().with_quantization(true))
+    }
+
+    pub fn with_options(options: SnapshotOptions) -> Self {
+        Self {
+            options,
+            history: DEFAULT_HISTORY,
+            clients: BTreeMap::new(),
+            tracker: None,
+        }
+    }
+
+    // How many unacknowledged deltas a client may fall behind before it is
+    // sent full state again.
+    pub fn with_history(mut self, history: usize) -> Self {
+        assert!(history > 0, "Replication history must hold at least one delta");
+        self.history = history;
+        self
+    }
+
+    pub fn add_client(&mut self, client: ClientId, relevance: Relevance) {
+        self.insert_client(client, ClientState::new(relevance, true));
+    }
+
+    pub fn add_unreliable_client(&mut self, client: ClientId, relevance: Relevance) {
+        self.insert_client(client, ClientState::new(relevance, false));
+    }
+
+    fn insert_client(&mut self, client: ClientId, state: ClientState) {
+        if self.clients.contains_key(&client) {
+            panic!("Client {} is already connected", client);
+        }
+        self.clients.insert(client, state);
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) -> bool {
+        self.clients.remove(&client).is_some()
+    }
+
+    pub fn has_client(&self, client: ClientId) -> bool {
+        self.clients.contains_key(&client)
+    }
+
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        self.clients.keys().copied().collect()
+    }
+
+    pub fn set_relevance(&mut self, client: ClientId, relevance: Relevance) {
+        self.client_mut(client).relevance = relevance;
+    }
+
+    // Subscribed entities are relevant regardless of the client's relevance.
+    pub fn subscribe(&mut self, client: ClientId, entity: EntityId) {
+        self.client_mut(client).subscriptions.insert(entity);
+    }
+
+    pub fn unsubscribe(&mut self, client: ClientId, entity: EntityId) -> bool {
+        self.client_mut(client).subscriptions.remove(&entity)
+    }
+
+    // Entities the client currently holds.
+    pub fn replicated_entities(&self, client: ClientId) -> Vec<EntityId> {
+        self.clients.get(&client).map(|state| state.baseline.entities()).unwrap_or_default()
+    }
+
+    // Forgets what the client was sent; its next delta carries full state.
+    pub fn reset_client(&mut self, client: ClientId) {
+        let state = self.client_mut(client);
+        state.baseline.clear();
+        state.base_timestamp = None;
+        state.epoch = state.next_sequence;
+        state.acked = None;
+        state.full = None;
+        state.unacked.clear();
+    }
+
+    // Records that the client applied `sequence`. Returns false for stale,
+    // unknown or future sequences.
+    pub fn acknowledge(&mut self, client: ClientId, sequence: u64) -> bool {
+        let state = self.client_mut(client);
+        if sequence >= state.next_sequence || state.acked.is_some_and(|acked| acked >= sequence) {
+            return false;
+        }
+        state.acked = Some(sequence);
+        state.unacked.retain(|touched| touched.sequence > sequence);
+        true
+    }
+
+    pub fn acknowledged(&self, client: ClientId) -> Option<u64> {
+        self.clients.get(&client).and_then(|state| state.acked)
+    }
+
+    // Drains the replicator's own change tracker once and builds every
+    // client's delta from it. Without a tracker in this world (the first
+    // update, say) every client starts over from full state.
+    pub fn update(&mut self, world: &mut World) -> BTreeMap<ClientId, SequencedDelta> {
+        let changes = match self.tracker.and_then(|tracker| world.take_changes_for(tracker)) {
+            Some(changes) => changes,
+            None => {
+                self.tracker = Some(world.track_changes());
+                let clients: Vec<ClientId> = self.clients.keys().copied().collect();
+                for client in clients {
+                    self.reset_client(client);
+                }
+                ChangeSet::default()
+            }
+        };
+        let timestamp = world.time();
+        let versions = world.component_registry().versions();
+
+        let mut deltas = BTreeMap::new();
+        for (client, state) in &mut self.clients {
+            let relevant = |entity: EntityId| {
+                state.subscriptions.contains(&entity) || state.relevance.evaluate(world, entity)
+            };
+            let sequence = state.next_sequence;
+            state.next_sequence += 1;
+
+            let first = state.base_timestamp.is_none();
+            let mut delta_changes = match first {
+                true => state.baseline.full_state(world, &self.options, &relevant),
+                false => state.baseline.update(world, &self.options, &changes, &relevant, true),
+            };
+            let touched = Touched::new(sequence, &delta_changes);
+            let base = if first { None } else { state.delta_base() };
+            match base {
+                None if !first => {
+                    delta_changes = state.baseline.full_state(world, &self.options, &relevant);
+                }
+                Some(_) if !state.unacked.is_empty() => {
+                    let mut entities = BTreeSet::new();
+                    let mut components = BTreeSet::new();
+                    for pending in &state.unacked {
+                        entities.extend(pending.entities.iter().copied());
+                        components.extend(pending.components.iter().cloned());
+                    }
+                    delta_changes.retain(|change| match change {
+                        DeltaChange::EntityAdded { entity_id } | DeltaChange::EntityRemoved { entity_id } => {
+                            !entities.contains(entity_id)
+                        }
+                        DeltaChange::ComponentAdded { entity_id, component_id, .. }
+                        | DeltaChange::ComponentUpdated { entity_id, component_id, .. }
+                        | DeltaChange::ComponentRemoved { entity_id, component_id }
+                        | DeltaChange::FieldsUpdated { entity_id, component_id, .. } => {
+                            !entities.contains(entity_id)
+                                && !components.contains(&(*entity_id, component_id.clone()))
+                        }
+                    });
+                    delta_changes.extend(state.baseline.restate(world, &self.options, &entities, &components));
+                }
+                _ => {}
+            }
+            if base.is_none() {
+                state.full = Some(sequence);
+            }
+
+            if state.reliable {
+                state.acked = Some(sequence);
+            } else {
+                state.unacked.push_back(touched);
+                if state.unacked.len() > self.history {
+                    state.unacked.pop_front();
+                }
+            }
+
+            deltas.insert(*client, SequencedDelta {
+                sequence,
+                base,
+                versions: versions.clone(),
+                format: self.options.format,
+                delta: Delta {
+                    changes: delta_changes,
+                    timestamp,
+                    base_timestamp: state.base_timestamp.replace(timestamp).unwrap_or(0.0),
+                },
+            });
+        }
+        deltas
+    }
+
+    fn client_mut(&mut self, client: ClientId) -> &mut ClientState {
+        self.clients.get_mut(&client)
+            .unwrap_or_else(|| panic!("Client {} is not connected", client))
+    }
+}
+
+impl Default for Replicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The client side of a replication stream: applies deltas in sequence order,
+// drops stale or duplicated ones and tracks what to acknowledge. Full state
+// clears the world first, so it should hold only replicated entities.
+// Deltas resent after loss may restate what the world already holds, which
+// shows up as mismatches in the report.
+#[derive(Debug, Default)]
+pub struct ReplicationReceiver {
+    applied: Option<u64>,
+}
+
+impl ReplicationReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The sequence to acknowledge.
+    pub fn applied(&self) -> Option<u64> {
+        self.applied
+    }
+
+    pub fn apply(&mut self, world: &mut World, delta: &SequencedDelta) -> Option<DeltaApplyReport> {
+        if self.applied.is_some_and(|applied| delta.sequence <= applied) {
+            return None;
+        }
+        match delta.base {
+            None => world.clear(),
+            Some(base) if self.applied.is_none_or(|applied| applied < base) => return None,
+            Some(_) => {}
+        }
+        world.component_registry_mut().set_source_versions(delta.versions.clone());
+        let report = world.apply_delta_with(&delta.delta, delta.format);
+        self.applied = Some(delta.sequence);
+        Some(report)
+    }
+}
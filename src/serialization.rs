@@ -1,58 +1,1102 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use serde::{Deserialize, Serialize};
-use crate::entity::EntityId;
-use crate::component::ComponentId;
+use crate::entity::{EntityId, advance_entity_id_counter};
+use crate::component::{ChangeSet, ChangeTracker, Component, ComponentId, ComponentRegistry, DynamicComponent, UnknownComponentPolicy, component_data_json};
+use crate::encoding::{self, BinaryFormat};
+use crate::error::TX2Error;
+use crate::patch;
+use crate::query::{Query, QueryDescriptor};
 use crate::world::World;
 use tx2_link::{
     SerializedComponent, SerializedEntity, WorldSnapshot, Delta, DeltaChange,
-    protocol::ComponentData,
+    protocol::{ComponentData, FieldDelta},
 };
+use serde_json::Value;
+
+pub const SNAPSHOT_FORMAT: &str = "tx2-core/1";
+
+// Carried in `WorldSnapshot::version` as JSON, since the tx2-link snapshot
+// has no other place for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub format: String,
+    pub schema_version: String,
+    pub tick: u64,
+    pub time: f64,
+    // Encoding of `ComponentData::Binary` payloads; JSON payloads are self-identifying.
+    #[serde(default = "default_encoding")]
+    pub encoding: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub component_versions: BTreeMap<ComponentId, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    // Set when a filter left entities or components out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+fn default_encoding() -> String {
+    encoding::format_name(BinaryFormat::Json).to_string()
+}
+
+impl SnapshotInfo {
+    // `None` for snapshots written before this information existed.
+    pub fn parse(snapshot: &WorldSnapshot) -> Option<SnapshotInfo> {
+        serde_json::from_str(&snapshot.version).ok()
+    }
+
+    pub fn binary_format(&self) -> Option<BinaryFormat> {
+        encoding::parse_format(&self.encoding)
+    }
+}
+
+// Selects the entities and components that go into a snapshot. Every
+// criterion that is set must pass; the empty filter keeps the whole world.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotFilter {
+    pub query: Option<QueryDescriptor>,
+    pub entities: Option<HashSet<EntityId>>,
+    pub include: Option<HashSet<ComponentId>>,
+    pub exclude: HashSet<ComponentId>,
+}
+
+impl SnapshotFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_query(mut self, descriptor: QueryDescriptor) -> Self {
+        self.query = Some(descriptor);
+        self
+    }
+
+    pub fn with_entities<I: IntoIterator<Item = EntityId>>(mut self, entities: I) -> Self {
+        self.entities = Some(entities.into_iter().collect());
+        self
+    }
+
+    pub fn include<I, S>(mut self, components: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<ComponentId>,
+    {
+        self.include.get_or_insert_with(HashSet::new)
+            .extend(components.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn exclude<I, S>(mut self, components: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<ComponentId>,
+    {
+        self.exclude.extend(components.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.query.is_none() && self.entities.is_none() && self.include.is_none() && self.exclude.is_empty()
+    }
+
+    pub fn keeps_component(&self, component_id: &str) -> bool {
+        !self.exclude.contains(component_id)
+            && self.include.as_ref().is_none_or(|include| include.contains(component_id))
+    }
+
+    fn filters_components(&self) -> bool {
+        self.include.is_some() || !self.exclude.is_empty()
+    }
+
+    fn compile_query(&self) -> Option<Query> {
+        self.query.as_ref()
+            .filter(|d| !(d.all.is_empty() && d.any.is_empty() && d.none.is_empty()))
+            .map(|d| Query::new(d.clone()))
+    }
+
+    fn admits_entity(&self, world: &World, query: Option<&Query>, entity_id: EntityId) -> bool {
+        world.has_entity(entity_id)
+            && self.entities.as_ref().is_none_or(|entities| entities.contains(&entity_id))
+            && query.is_none_or(|query| query.matches(entity_id, &world.component_store))
+    }
+
+    // Matching entity ids in ascending order.
+    fn select_entities(&self, world: &World) -> Vec<EntityId> {
+        let query = self.compile_query();
+        let mut ids: Vec<EntityId> = match &self.entities {
+            Some(entities) => entities.iter().copied().filter(|id| world.has_entity(*id)).collect(),
+            None => world.get_all_entities().iter().map(|e| e.id).collect(),
+        };
+        if let Some(query) = &query {
+            ids.retain(|id| query.matches(*id, &world.component_store));
+        }
+        ids.sort_unstable();
+        ids
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotOptions {
+    pub format: BinaryFormat,
+    pub metadata: BTreeMap<String, String>,
+    pub filter: SnapshotFilter,
+    // Applies registered field quantization. Lossy, so only replication
+    // turns it on; plain snapshots and save files keep exact values.
+    pub quantize: bool,
+}
+
+impl SnapshotOptions {
+    pub fn new() -> Self {
+        Self {
+            format: BinaryFormat::Json,
+            metadata: BTreeMap::new(),
+            filter: SnapshotFilter::new(),
+            quantize: false,
+        }
+    }
+
+    pub fn with_format(mut self, format: BinaryFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_filter(mut self, filter: SnapshotFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_quantization(mut self, quantize: bool) -> Self {
+        self.quantize = quantize;
+        self
+    }
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct Serializer;
 
 impl Serializer {
     pub fn create_snapshot(world: &World) -> WorldSnapshot {
+        Self::create_snapshot_with(world, &SnapshotOptions::new())
+    }
+
+    pub fn create_snapshot_with_metadata(world: &World, metadata: BTreeMap<String, String>) -> WorldSnapshot {
+        Self::create_snapshot_with(world, &SnapshotOptions { metadata, ..SnapshotOptions::new() })
+    }
+
+    pub fn create_snapshot_filtered(world: &World, filter: &SnapshotFilter) -> WorldSnapshot {
+        Self::create_snapshot_with(world, &SnapshotOptions::new().with_filter(filter.clone()))
+    }
+
+    pub fn create_snapshot_with(world: &World, options: &SnapshotOptions) -> WorldSnapshot {
+        let filter = &options.filter;
+        let registry = world.component_registry();
         let mut entities = Vec::new();
-        
-        for entity in world.get_all_entities() {
+
+        for entity_id in filter.select_entities(world) {
+            let components = world.get_all_components(entity_id);
             let mut serialized_components = Vec::new();
-            for component in world.get_all_components(entity.id) {
+            for component in &components {
+                let id = component.component_id();
+                if registry.is_transient(&id) || !filter.keeps_component(&id) {
+                    continue;
+                }
                 serialized_components.push(SerializedComponent {
-                    id: component.component_id(),
-                    data: ComponentData::from_json_value(component.to_json()),
+                    id,
+                    data: encode_component(registry, component.as_ref(), options),
                 });
             }
-            
+
+            // Entities whose components were all filtered away carry nothing.
+            if serialized_components.is_empty() && !components.is_empty() && filter.filters_components() {
+                continue;
+            }
             entities.push(SerializedEntity {
-                id: entity.id,
+                id: entity_id,
                 components: serialized_components,
             });
         }
 
+        let info = SnapshotInfo {
+            format: SNAPSHOT_FORMAT.to_string(),
+            schema_version: world.component_registry().schema_version(),
+            tick: world.frame_count(),
+            time: world.time(),
+            encoding: encoding::format_name(options.format).to_string(),
+            component_versions: world.component_registry().versions(),
+            metadata: options.metadata.clone(),
+            partial: !filter.is_empty(),
+        };
+
         WorldSnapshot {
             entities,
-            timestamp: 0.0,
-            version: "1.0.0".to_string(),
+            timestamp: world.time(),
+            version: serde_json::to_string(&info).unwrap_or_default(),
+        }
+    }
+}
+
+// JSON stays `ComponentData::Json` so tx2-link can diff it field by field.
+// Components that cannot be encoded in the requested format fall back to JSON.
+fn encode_component(registry: &ComponentRegistry, component: &dyn Component, options: &SnapshotOptions) -> ComponentData {
+    let format = options.format;
+    // Quantized components go through their quantized JSON form. Bincode
+    // cannot carry an untyped value and its fixed-size fields are already
+    // compact, so bincode components are sent whole.
+    let quantized = registry.get(&component.component_id())
+        .filter(|r| options.quantize && r.is_quantized());
+    if let Some(registration) = quantized.filter(|_| format != BinaryFormat::Bincode) {
+        let mut value = component.to_json();
+        registration.quantize(&mut value);
+        return match encoding::encode(&value, format) {
+            Ok(bytes) if format != BinaryFormat::Json => ComponentData::Binary(bytes),
+            _ => ComponentData::from_json_value(value),
+        };
+    }
+    if format == BinaryFormat::Json {
+        return ComponentData::from_json_value(component.to_json());
+    }
+    match component.encode(format) {
+        Ok(bytes) => ComponentData::Binary(bytes),
+        Err(e) => {
+            log::warn!("Falling back to JSON for {}: {}", component.component_id(), e);
+            ComponentData::from_json_value(component.to_json())
+        }
+    }
+}
+
+// The JSON form peers see: quantized fields are already rounded, so changes
+// below the quantization step produce no patches.
+fn replicated_json(registry: &ComponentRegistry, component: &dyn Component, options: &SnapshotOptions) -> Value {
+    let mut value = component.to_json();
+    if let Some(registration) = registry.get(&component.component_id()).filter(|_| options.quantize) {
+        registration.quantize(&mut value);
+    }
+    value
+}
+
+// Outcome of restoring a snapshot into a world.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreReport {
+    pub entities: usize,
+    pub components: usize,
+    pub skipped: Vec<(EntityId, ComponentId)>,
+    pub dynamic: Vec<(EntityId, ComponentId)>,
+    pub rebuilt: usize,
+    pub info: Option<SnapshotInfo>,
+    pub warnings: Vec<String>,
+}
+
+impl Serializer {
+    // Rebuilds the world's entities and components from a snapshot. Every
+    // component is decoded before the world is touched, so a failed restore
+    // leaves the world as it was. Resources are kept.
+    //
+    // A partial (filtered) snapshot is merged instead: its entities are
+    // created if missing and its components replace the ones they hold.
+    // Everything else, the clock and the source versions included, is kept.
+    pub fn restore_snapshot(
+        world: &mut World,
+        snapshot: &WorldSnapshot,
+        policy: UnknownComponentPolicy,
+    ) -> Result<RestoreReport, TX2Error> {
+        let mut report = RestoreReport::default();
+        check_compatibility(world, snapshot, &mut report)?;
+        let source_versions = report.info.as_ref()
+            .map(|info| info.component_versions.clone())
+            .unwrap_or_default();
+        let format = report.info.as_ref()
+            .and_then(|info| info.binary_format())
+            .unwrap_or(BinaryFormat::Json);
+        let mut restored = Vec::with_capacity(snapshot.entities.len());
+
+        for entity in &snapshot.entities {
+            let mut components: Vec<Box<dyn Component>> = Vec::new();
+            for component in &entity.components {
+                if world.component_registry().is_transient(&component.id) {
+                    report.skipped.push((entity.id, component.id.clone()));
+                    continue;
+                }
+                let decode_error = |message| TX2Error::ComponentDeserialize {
+                    entity_id: entity.id,
+                    component_id: component.id.clone(),
+                    message,
+                };
+                match world.component_registry().get(&component.id) {
+                    Some(registration) => {
+                        let from_version = source_versions.get(&component.id).copied().unwrap_or(1);
+                        let typed = registration.decode(&component.data, format, from_version).map_err(decode_error)?;
+                        components.push(typed);
+                    }
+                    None => match policy {
+                        UnknownComponentPolicy::Error => {
+                            return Err(TX2Error::UnknownComponent {
+                                entity_id: entity.id,
+                                component_id: component.id.clone(),
+                            });
+                        }
+                        UnknownComponentPolicy::Skip => {
+                            report.skipped.push((entity.id, component.id.clone()));
+                        }
+                        UnknownComponentPolicy::KeepDynamic => {
+                            let data = component_data_json(&component.data, format).map_err(decode_error)?;
+                            report.dynamic.push((entity.id, component.id.clone()));
+                            components.push(Box::new(DynamicComponent {
+                                id: component.id.clone(),
+                                data,
+                            }));
+                        }
+                    },
+                }
+            }
+            let present: HashSet<ComponentId> = components.iter().map(|c| c.component_id()).collect();
+            let defaults = world.component_registry().rebuild_defaults(&present);
+            restored.push((entity.id, components, defaults));
+        }
+
+        let partial = report.info.as_ref().is_some_and(|info| info.partial);
+        if !partial {
+            world.clear();
+            world.component_registry_mut().set_source_versions(source_versions);
+            world.component_registry_mut().set_source_format(format);
+            if let Some(info) = &report.info {
+                world.set_clock(info.time, info.tick);
+            }
+        }
+        for (entity_id, components, defaults) in restored {
+            if !world.has_entity(entity_id) {
+                world.create_entity_with_id(entity_id);
+                advance_entity_id_counter(entity_id);
+            }
+            report.entities += 1;
+            for component in components {
+                world.add_component(entity_id, component);
+                report.components += 1;
+            }
+            for component in defaults {
+                if !world.has_component(entity_id, &component.component_id()) {
+                    world.add_component(entity_id, component);
+                    report.rebuilt += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+// A different format is an error; a different component schema is only a
+// warning, since unknown components are already handled by the policy.
+fn check_compatibility(world: &World, snapshot: &WorldSnapshot, report: &mut RestoreReport) -> Result<(), TX2Error> {
+    let Some(info) = SnapshotInfo::parse(snapshot) else {
+        report.warnings.push(format!("Snapshot version {} carries no schema information", snapshot.version));
+        return Ok(());
+    };
+    if info.format != SNAPSHOT_FORMAT {
+        return Err(TX2Error::IncompatibleSnapshot {
+            expected: SNAPSHOT_FORMAT.to_string(),
+            found: info.format,
+        });
+    }
+    let schema_version = world.component_registry().schema_version();
+    if info.schema_version != schema_version {
+        report.warnings.push(format!(
+            "Snapshot schema {} differs from registered schema {}",
+            info.schema_version, schema_version
+        ));
+    }
+    for warning in &report.warnings {
+        log::warn!("{}", warning);
+    }
+    report.info = Some(info);
+    Ok(())
+}
+
+// A delta change whose preconditions did not hold against the world.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeltaMismatch {
+    EntityExists(EntityId),
+    EntityMissing(EntityId),
+    ComponentExists { entity_id: EntityId, component_id: ComponentId },
+    ComponentMissing { entity_id: EntityId, component_id: ComponentId },
+    UnknownComponent { entity_id: EntityId, component_id: ComponentId },
+    InvalidData { entity_id: EntityId, component_id: ComponentId, message: String },
+    StaleField { entity_id: EntityId, component_id: ComponentId, field_id: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeltaApplyReport {
+    pub applied: usize,
+    pub skipped: Vec<(EntityId, ComponentId)>,
+    pub mismatches: Vec<DeltaMismatch>,
+}
+
+impl DeltaApplyReport {
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Serializer {
+    // Applies each change in order. Mismatches are reported rather than
+    // fatal; where the intent is unambiguous the world still converges on
+    // the sender's state (an added component that already exists replaces
+    // it, an update to a missing component adds it, a stale field is
+    // overwritten). Changes to missing entities are dropped.
+    pub fn apply_delta(world: &mut World, delta: &Delta) -> DeltaApplyReport {
+        let format = world.component_registry().source_format();
+        Self::apply_delta_with(world, delta, format)
+    }
+
+    // Like `apply_delta`, with binary component data decoded as `format`
+    // instead of the format of the last restored snapshot.
+    pub fn apply_delta_with(world: &mut World, delta: &Delta, format: BinaryFormat) -> DeltaApplyReport {
+        let mut report = DeltaApplyReport::default();
+        for change in &delta.changes {
+            if let Some((entity_id, component_id)) = changed_component(change) {
+                if world.component_registry().is_transient(component_id) {
+                    report.skipped.push((entity_id, component_id.clone()));
+                    continue;
+                }
+            }
+            if apply_change(world, change, format, &mut report) {
+                report.applied += 1;
+            }
+        }
+        report
+    }
+}
+
+fn changed_component(change: &DeltaChange) -> Option<(EntityId, &ComponentId)> {
+    match change {
+        DeltaChange::EntityAdded { .. } | DeltaChange::EntityRemoved { .. } => None,
+        DeltaChange::ComponentAdded { entity_id, component_id, .. }
+        | DeltaChange::ComponentUpdated { entity_id, component_id, .. }
+        | DeltaChange::ComponentRemoved { entity_id, component_id }
+        | DeltaChange::FieldsUpdated { entity_id, component_id, .. } => Some((*entity_id, component_id)),
+    }
+}
+
+fn apply_change(world: &mut World, change: &DeltaChange, format: BinaryFormat, report: &mut DeltaApplyReport) -> bool {
+    match change {
+        DeltaChange::EntityAdded { entity_id } => {
+            if world.has_entity(*entity_id) {
+                report.mismatches.push(DeltaMismatch::EntityExists(*entity_id));
+                return false;
+            }
+            world.create_entity_with_id(*entity_id);
+            advance_entity_id_counter(*entity_id);
+            true
         }
+        DeltaChange::EntityRemoved { entity_id } => {
+            if !world.destroy_entity(*entity_id) {
+                report.mismatches.push(DeltaMismatch::EntityMissing(*entity_id));
+                return false;
+            }
+            true
+        }
+        DeltaChange::ComponentAdded { entity_id, component_id, data }
+        | DeltaChange::ComponentUpdated { entity_id, component_id, data } => {
+            if !world.has_entity(*entity_id) {
+                report.mismatches.push(DeltaMismatch::EntityMissing(*entity_id));
+                return false;
+            }
+            let exists = world.has_component(*entity_id, component_id);
+            let adding = matches!(change, DeltaChange::ComponentAdded { .. });
+            if adding && exists {
+                report.mismatches.push(DeltaMismatch::ComponentExists {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                });
+            } else if !adding && !exists {
+                report.mismatches.push(DeltaMismatch::ComponentMissing {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                });
+            }
+            let from_version = world.component_registry().source_version(component_id);
+            replace_component(world, *entity_id, component_id, data, format, from_version, report)
+        }
+        DeltaChange::ComponentRemoved { entity_id, component_id } => {
+            if !world.has_entity(*entity_id) {
+                report.mismatches.push(DeltaMismatch::EntityMissing(*entity_id));
+                return false;
+            }
+            if !world.remove_component(*entity_id, component_id) {
+                report.mismatches.push(DeltaMismatch::ComponentMissing {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                });
+                return false;
+            }
+            true
+        }
+        DeltaChange::FieldsUpdated { entity_id, component_id, fields } => {
+            if !world.has_entity(*entity_id) {
+                report.mismatches.push(DeltaMismatch::EntityMissing(*entity_id));
+                return false;
+            }
+            // Patched values may be quantized; decoding dequantizes them and
+            // leaves the exact local values around them alone.
+            let current = world.get_all_components(*entity_id).into_iter()
+                .find(|c| c.component_id() == *component_id)
+                .map(|c| c.to_json());
+            let Some(mut value) = current else {
+                report.mismatches.push(DeltaMismatch::ComponentMissing {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                });
+                return false;
+            };
+            // Field names from an older schema cannot be patched onto migrated data.
+            if let Some(registration) = world.component_registry().get(component_id) {
+                let from_version = world.component_registry().source_version(component_id);
+                if from_version != registration.version {
+                    report.mismatches.push(DeltaMismatch::InvalidData {
+                        entity_id: *entity_id,
+                        component_id: component_id.clone(),
+                        message: format!(
+                            "field update written at version {} cannot be applied to version {}",
+                            from_version, registration.version
+                        ),
+                    });
+                    return false;
+                }
+            }
+            for field in fields {
+                let matched = if patch::is_pointer(&field.field_id) {
+                    patch::apply(&mut value, field).is_ok()
+                } else {
+                    apply_field(&mut value, field)
+                };
+                if !matched {
+                    report.mismatches.push(DeltaMismatch::StaleField {
+                        entity_id: *entity_id,
+                        component_id: component_id.clone(),
+                        field_id: field.field_id.clone(),
+                    });
+                }
+            }
+            let version = world.component_registry().get(component_id).map_or(1, |r| r.version);
+            let data = ComponentData::from_json_value(value);
+            replace_component(world, *entity_id, component_id, &data, format, version, report)
+        }
+    }
+}
+
+// Sets the field and returns whether its previous value matched `old_value`.
+fn apply_field(value: &mut Value, field: &FieldDelta) -> bool {
+    if !value.is_object() {
+        *value = Value::Object(Default::default());
     }
+    let Some(object) = value.as_object_mut() else {
+        return false;
+    };
+    let matched = match &field.old_value {
+        Some(old) => object.get(&field.field_id) == Some(&field_value_to_json(old)),
+        None => !object.contains_key(&field.field_id),
+    };
+    object.insert(field.field_id.clone(), field_value_to_json(&field.new_value));
+    matched
 }
 
+// Decodes the value (binary data as `format`) through the registry,
+// migrating it from `from_version`, or the unknown-component policy, and
+// swaps it in for any existing component with the same id.
+fn replace_component(
+    world: &mut World,
+    entity_id: EntityId,
+    component_id: &ComponentId,
+    data: &ComponentData,
+    format: BinaryFormat,
+    from_version: u32,
+    report: &mut DeltaApplyReport,
+) -> bool {
+    let invalid = |message| DeltaMismatch::InvalidData {
+        entity_id,
+        component_id: component_id.clone(),
+        message,
+    };
+    let component: Box<dyn Component> = match world.component_registry().get(component_id) {
+        Some(registration) => match registration.decode(data, format, from_version) {
+            Ok(component) => component,
+            Err(message) => {
+                report.mismatches.push(invalid(message));
+                return false;
+            }
+        },
+        None => match world.component_registry().unknown_policy() {
+            UnknownComponentPolicy::Error => {
+                report.mismatches.push(DeltaMismatch::UnknownComponent {
+                    entity_id,
+                    component_id: component_id.clone(),
+                });
+                return false;
+            }
+            UnknownComponentPolicy::Skip => {
+                report.skipped.push((entity_id, component_id.clone()));
+                return false;
+            }
+            UnknownComponentPolicy::KeepDynamic => match component_data_json(data, format) {
+                Ok(value) => Box::new(DynamicComponent {
+                    id: component_id.clone(),
+                    data: value,
+                }),
+                Err(message) => {
+                    report.mismatches.push(invalid(message));
+                    return false;
+                }
+            },
+        },
+    };
+    world.remove_component(entity_id, component_id);
+    world.add_component(entity_id, component);
+    true
+}
+
+pub use crate::patch::field_value_to_json;
+
+// Diffs a full snapshot of the world against the previous one on every
+// call, so each delta costs as much as the world. Prefer
+// `TrackedDeltaCompressor`, which builds deltas from change tracking. Both
+// assume every delta arrives; over unreliable transports use
+// `replication::Replicator`, whose deltas build on acknowledged state.
+//
+// Modified components are sent as JSON-pointer patches (see `patch`) when
+// the patch is smaller than the component. Bincode data cannot be read back
+// without its type, so bincode components are always sent whole.
 pub struct DeltaCompressor {
-    inner: tx2_link::DeltaCompressor,
+    options: SnapshotOptions,
+    previous: Option<WorldSnapshot>,
 }
 
 impl DeltaCompressor {
     pub fn new() -> Self {
+        Self::with_options(SnapshotOptions::new().with_quantization(true))
+    }
+
+    pub fn with_format(format: BinaryFormat) -> Self {
+        Self::with_options(SnapshotOptions::new().with_format(format).with_quantization(true))
+    }
+
+    pub fn with_options(options: SnapshotOptions) -> Self {
         Self {
-            inner: tx2_link::DeltaCompressor::new(),
+            options,
+            previous: None,
         }
     }
 
     pub fn create_delta(&mut self, world: &World) -> Delta {
-        let snapshot = Serializer::create_snapshot(world);
-        self.inner.create_delta(snapshot)
+        let snapshot = Serializer::create_snapshot_with(world, &self.options);
+        let changes = match &self.previous {
+            Some(previous) => self.diff(previous, &snapshot),
+            None => snapshot.entities.iter()
+                .flat_map(|entity| {
+                    std::iter::once(DeltaChange::EntityAdded { entity_id: entity.id })
+                        .chain(entity.components.iter().map(|c| DeltaChange::ComponentAdded {
+                            entity_id: entity.id,
+                            component_id: c.id.clone(),
+                            data: c.data.clone(),
+                        }))
+                })
+                .collect(),
+        };
+        let delta = Delta {
+            changes,
+            timestamp: snapshot.timestamp,
+            base_timestamp: self.previous.as_ref().map_or(0.0, |previous| previous.timestamp),
+        };
+        self.previous = Some(snapshot);
+        delta
+    }
+
+    fn diff(&self, previous: &WorldSnapshot, current: &WorldSnapshot) -> Vec<DeltaChange> {
+        let previous_entities: HashMap<EntityId, &SerializedEntity> = previous.entities.iter()
+            .map(|entity| (entity.id, entity))
+            .collect();
+        let current_ids: HashSet<EntityId> = current.entities.iter().map(|entity| entity.id).collect();
+        let format = self.options.format;
+
+        let mut changes = Vec::new();
+        for entity in &current.entities {
+            let Some(previous_entity) = previous_entities.get(&entity.id) else {
+                changes.push(DeltaChange::EntityAdded { entity_id: entity.id });
+                changes.extend(entity.components.iter().map(|c| DeltaChange::ComponentAdded {
+                    entity_id: entity.id,
+                    component_id: c.id.clone(),
+                    data: c.data.clone(),
+                }));
+                continue;
+            };
+            let previous_components: HashMap<&str, &ComponentData> = previous_entity.components.iter()
+                .map(|c| (c.id.as_str(), &c.data))
+                .collect();
+            for component in &entity.components {
+                let Some(previous_data) = previous_components.get(component.id.as_str()) else {
+                    changes.push(DeltaChange::ComponentAdded {
+                        entity_id: entity.id,
+                        component_id: component.id.clone(),
+                        data: component.data.clone(),
+                    });
+                    continue;
+                };
+                if **previous_data == component.data {
+                    continue;
+                }
+                // Only modified components are decoded for a patch.
+                let json = component_data_json(previous_data, format).ok()
+                    .zip(component_data_json(&component.data, format).ok());
+                changes.push(match json {
+                    Some((previous_json, current_json)) => update_change(
+                        entity.id, component.id.clone(), &previous_json, &current_json, component.data.clone(),
+                    ),
+                    None => DeltaChange::ComponentUpdated {
+                        entity_id: entity.id,
+                        component_id: component.id.clone(),
+                        data: component.data.clone(),
+                    },
+                });
+            }
+            for previous_component in &previous_entity.components {
+                if !entity.components.iter().any(|c| c.id == previous_component.id) {
+                    changes.push(DeltaChange::ComponentRemoved {
+                        entity_id: entity.id,
+                        component_id: previous_component.id.clone(),
+                    });
+                }
+            }
+        }
+        for previous_entity in &previous.entities {
+            if !current_ids.contains(&previous_entity.id) {
+                changes.push(DeltaChange::EntityRemoved { entity_id: previous_entity.id });
+            }
+        }
+        changes
+    }
+
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+// A field patch when one is smaller than the whole component, otherwise a
+// full update.
+fn update_change(
+    entity_id: EntityId,
+    component_id: ComponentId,
+    previous: &Value,
+    current: &Value,
+    data: ComponentData,
+) -> DeltaChange {
+    if let Some(fields) = patch::diff(previous, current) {
+        let patch_size: usize = fields.iter()
+            .map(|field| field.field_id.len() + field_value_to_json(&field.new_value).to_string().len() + 2)
+            .sum();
+        let full_size = match &data {
+            ComponentData::Json(json) => json.len(),
+            ComponentData::Binary(bytes) => bytes.len(),
+            ComponentData::Structured(_) => usize::MAX,
+        };
+        if !fields.is_empty() && patch_size < full_size {
+            return DeltaChange::FieldsUpdated { entity_id, component_id, fields };
+        }
+    }
+    DeltaChange::ComponentUpdated { entity_id, component_id, data }
+}
+
+// What one receiver has been sent: the replicated JSON of every component,
+// so later changes can be diffed without a full snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    sent: HashMap<EntityId, HashMap<ComponentId, Value>>,
+}
+
+impl Baseline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.sent.contains_key(&entity_id)
+    }
+
+    pub fn entities(&self) -> Vec<EntityId> {
+        let mut entities: Vec<EntityId> = self.sent.keys().copied().collect();
+        entities.sort_unstable();
+        entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.sent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty()
     }
 
+    pub fn clear(&mut self) {
+        self.sent.clear();
+    }
+
+    // Full state of every entity the options and `relevant` admit.
+    pub fn full_state(
+        &mut self,
+        world: &World,
+        options: &SnapshotOptions,
+        relevant: &dyn Fn(EntityId) -> bool,
+    ) -> Vec<DeltaChange> {
+        self.sent.clear();
+        let mut changes = Vec::new();
+        for entity_id in options.filter.select_entities(world) {
+            if relevant(entity_id) {
+                self.add_entity(world, options, entity_id, &mut changes);
+            }
+        }
+        changes
+    }
+
+    // Brings the receiver up to date with `changes`. Entities that stop being
+    // admitted are despawned and entities that start are sent in full. With
+    // `rescan`, every entity is checked, for relevance that can change
+    // without the entity itself changing; otherwise only changed ones are.
+    pub fn update(
+        &mut self,
+        world: &World,
+        options: &SnapshotOptions,
+        changes: &ChangeSet,
+        relevant: &dyn Fn(EntityId) -> bool,
+        rescan: bool,
+    ) -> Vec<DeltaChange> {
+        let mut touched: BTreeMap<EntityId, Vec<(&ComponentId, bool)>> = BTreeMap::new();
+        for entity_id in changes.spawned.iter().chain(&changes.despawned) {
+            touched.entry(*entity_id).or_default();
+        }
+        for (entity_id, component_id) in &changes.removed {
+            touched.entry(*entity_id).or_default().push((component_id, false));
+        }
+        for (entity_id, component_id) in changes.added.iter().chain(&changes.changed) {
+            touched.entry(*entity_id).or_default().push((component_id, true));
+        }
+        if rescan {
+            for entity_id in self.sent.keys().copied().chain(options.filter.select_entities(world)) {
+                touched.entry(entity_id).or_default();
+            }
+        }
+
+        let query = options.filter.compile_query();
+        let mut delta_changes = Vec::new();
+        for (entity_id, components) in touched {
+            let included = options.filter.admits_entity(world, query.as_ref(), entity_id) && relevant(entity_id);
+            let respawned = changes.despawned.contains(&entity_id) && changes.spawned.contains(&entity_id);
+            let was_sent = self.sent.contains_key(&entity_id);
+
+            if was_sent && (!included || respawned) {
+                self.sent.remove(&entity_id);
+                delta_changes.push(DeltaChange::EntityRemoved { entity_id });
+            }
+            if !included {
+                continue;
+            }
+            if !was_sent || respawned {
+                self.add_entity(world, options, entity_id, &mut delta_changes);
+                continue;
+            }
+            for (component_id, present) in components {
+                self.diff_component(world, options, entity_id, component_id, present, &mut delta_changes);
+            }
+        }
+        delta_changes
+    }
+
+    // Absolute statements of the current state of the given entities and
+    // components, for a receiver whose state for them is unknown: held
+    // entities are removed and re-added with all their components, so no
+    // component the receiver may still hold survives, and the rest removed.
+    pub fn restate(
+        &self,
+        world: &World,
+        options: &SnapshotOptions,
+        entities: &BTreeSet<EntityId>,
+        components: &BTreeSet<(EntityId, ComponentId)>,
+    ) -> Vec<DeltaChange> {
+        let registry = world.component_registry();
+        let data = |entity_id: EntityId, component_id: &ComponentId| {
+            world.get_all_components(entity_id).into_iter()
+                .find(|c| c.component_id() == *component_id)
+                .map(|c| encode_component(registry, c.as_ref(), options))
+        };
+
+        let mut changes = Vec::new();
+        for entity_id in entities {
+            let Some(sent) = self.sent.get(entity_id) else {
+                changes.push(DeltaChange::EntityRemoved { entity_id: *entity_id });
+                continue;
+            };
+            changes.push(DeltaChange::EntityRemoved { entity_id: *entity_id });
+            changes.push(DeltaChange::EntityAdded { entity_id: *entity_id });
+            let mut component_ids: Vec<&ComponentId> = sent.keys().collect();
+            component_ids.sort();
+            for component_id in component_ids {
+                if let Some(data) = data(*entity_id, component_id) {
+                    changes.push(DeltaChange::ComponentAdded {
+                        entity_id: *entity_id,
+                        component_id: component_id.clone(),
+                        data,
+                    });
+                }
+            }
+        }
+        for (entity_id, component_id) in components {
+            if entities.contains(entity_id) {
+                continue;
+            }
+            let Some(sent) = self.sent.get(entity_id) else {
+                continue;
+            };
+            match sent.contains_key(component_id).then(|| data(*entity_id, component_id)).flatten() {
+                Some(data) => changes.push(DeltaChange::ComponentUpdated {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                    data,
+                }),
+                None => changes.push(DeltaChange::ComponentRemoved {
+                    entity_id: *entity_id,
+                    component_id: component_id.clone(),
+                }),
+            }
+        }
+        changes
+    }
+
+    fn add_entity(&mut self, world: &World, options: &SnapshotOptions, entity_id: EntityId, changes: &mut Vec<DeltaChange>) {
+        changes.push(DeltaChange::EntityAdded { entity_id });
+        self.sent.insert(entity_id, HashMap::new());
+        let mut component_ids: Vec<ComponentId> = world.get_all_components(entity_id).iter()
+            .map(|c| c.component_id())
+            .collect();
+        component_ids.sort();
+        component_ids.dedup();
+        for component_id in &component_ids {
+            self.diff_component(world, options, entity_id, component_id, true, changes);
+        }
+    }
+
+    fn diff_component(
+        &mut self,
+        world: &World,
+        options: &SnapshotOptions,
+        entity_id: EntityId,
+        component_id: &ComponentId,
+        present: bool,
+        changes: &mut Vec<DeltaChange>,
+    ) {
+        if world.component_registry().is_transient(component_id) || !options.filter.keeps_component(component_id) {
+            return;
+        }
+        let sent = self.sent.entry(entity_id).or_default();
+        let current = world.get_all_components(entity_id).into_iter()
+            .find(|c| c.component_id() == *component_id)
+            .filter(|_| present);
+
+        let Some(component) = current else {
+            if sent.remove(component_id).is_some() {
+                changes.push(DeltaChange::ComponentRemoved {
+                    entity_id,
+                    component_id: component_id.clone(),
+                });
+            }
+            return;
+        };
+
+        let registry = world.component_registry();
+        let json = replicated_json(registry, component.as_ref(), options);
+        if sent.get(component_id) == Some(&json) {
+            return;
+        }
+        let data = encode_component(registry, component.as_ref(), options);
+        let change = match sent.get(component_id) {
+            Some(previous) => update_change(entity_id, component_id.clone(), previous, &json, data),
+            None => DeltaChange::ComponentAdded {
+                entity_id,
+                component_id: component_id.clone(),
+                data,
+            },
+        };
+        sent.insert(component_id.clone(), json);
+        changes.push(change);
+    }
+}
+
+// Builds deltas from the world's change tracking instead of diffing full
+// snapshots, so the cost follows what changed rather than the world size.
+// The first delta starts the compressor's own change tracker and carries the
+// whole (filtered) world, as does any delta after the tracker goes missing.
+// Updates are sent as field patches in any format.
+pub struct TrackedDeltaCompressor {
+    options: SnapshotOptions,
+    baseline: Baseline,
+    base_timestamp: Option<f64>,
+    tracker: Option<ChangeTracker>,
+}
+
+impl TrackedDeltaCompressor {
+    pub fn new() -> Self {
+        Self::with_options(SnapshotOptions::new().with_quantization(true))
+    }
+
+    pub fn with_format(format: BinaryFormat) -> Self {
+        Self::with_options(SnapshotOptions::new().with_format(format).with_quantization(true))
+    }
+
+    pub fn with_options(options: SnapshotOptions) -> Self {
+        Self {
+            options,
+            baseline: Baseline::new(),
+            base_timestamp: None,
+            tracker: None,
+        }
+    }
+
+    pub fn create_delta(&mut self, world: &mut World) -> Delta {
+        let timestamp = world.time();
+        let base_timestamp = self.base_timestamp.replace(timestamp);
+        let tracked = self.tracker.and_then(|tracker| world.take_changes_for(tracker));
+        let changes = match (base_timestamp, tracked) {
+            (Some(_), Some(changes)) => self.baseline.update(world, &self.options, &changes, &|_| true, false),
+            (_, tracked) => {
+                if tracked.is_none() {
+                    self.tracker = Some(world.track_changes());
+                }
+                self.baseline.full_state(world, &self.options, &|_| true)
+            }
+        };
+        Delta {
+            changes,
+            timestamp,
+            base_timestamp: base_timestamp.unwrap_or(0.0),
+        }
+    }
+
+    pub fn baseline(&self) -> &Baseline {
+        &self.baseline
+    }
+
+    // Forgets what was sent; the next delta carries the whole world again.
     pub fn reset(&mut self) {
-        self.inner.reset();
+        self.baseline.clear();
+        self.base_timestamp = None;
+    }
+}
+
+impl Default for TrackedDeltaCompressor {
+    fn default() -> Self {
+        Self::new()
     }
 }
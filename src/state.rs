@@ -0,0 +1,103 @@
+use std::any::type_name;
+use std::fmt::Debug;
+use std::hash::Hash;
+use crate::error::TX2Error;
+use crate::system::{SystemPhase, SystemScheduler};
+use crate::world::World;
+
+pub trait States: Clone + PartialEq + Eq + Hash + Debug + Send + Sync + 'static {}
+
+impl<S> States for S where S: Clone + PartialEq + Eq + Hash + Debug + Send + Sync + 'static {}
+
+// Current value of an application state machine, stored as a resource.
+#[derive(Debug, Clone)]
+pub struct State<S: States> {
+    current: S,
+    previous: Option<S>,
+    entered: bool,
+}
+
+impl<S: States> State<S> {
+    pub fn new(initial: S) -> Self {
+        Self {
+            current: initial,
+            previous: None,
+            entered: false,
+        }
+    }
+
+    pub fn get(&self) -> &S {
+        &self.current
+    }
+
+    pub fn previous(&self) -> Option<&S> {
+        self.previous.as_ref()
+    }
+}
+
+// Transitions requested during a frame; applied in order at the start of the next one.
+#[derive(Debug, Clone)]
+pub struct NextState<S: States> {
+    queue: Vec<S>,
+}
+
+impl<S: States> NextState<S> {
+    pub fn set(&mut self, state: S) {
+        self.queue.push(state);
+    }
+
+    pub fn pending(&self) -> &[S] {
+        &self.queue
+    }
+}
+
+impl<S: States> Default for NextState<S> {
+    fn default() -> Self {
+        Self { queue: Vec::new() }
+    }
+}
+
+pub fn on_enter<S: States>(state: &S) -> SystemPhase {
+    SystemPhase::custom(&format!("onEnter({}::{:?})", type_name::<S>(), state))
+}
+
+pub fn on_exit<S: States>(state: &S) -> SystemPhase {
+    SystemPhase::custom(&format!("onExit({}::{:?})", type_name::<S>(), state))
+}
+
+pub(crate) fn apply_transitions<S: States>(
+    scheduler: &mut SystemScheduler,
+    world: &mut World,
+    delta_time: f64,
+    time: f64,
+) -> Result<(), TX2Error> {
+    let Some(state) = world.get_resource_mut::<State<S>>() else {
+        return Ok(());
+    };
+    if !state.entered {
+        state.entered = true;
+        let initial = state.current.clone();
+        scheduler.run_state_schedule(on_enter(&initial), world, delta_time, time)?;
+    }
+
+    // Transitions queued by OnEnter/OnExit systems wait for the next frame.
+    let pending = world.get_resource_mut::<NextState<S>>()
+        .map(|next| std::mem::take(&mut next.queue))
+        .unwrap_or_default();
+
+    for next in pending {
+        let Some(current) = world.get_resource::<State<S>>().map(|s| s.current.clone()) else {
+            break;
+        };
+        if current == next {
+            continue;
+        }
+        scheduler.run_state_schedule(on_exit(&current), world, delta_time, time)?;
+        if let Some(state) = world.get_resource_mut::<State<S>>() {
+            state.previous = Some(current);
+            state.current = next.clone();
+        }
+        scheduler.run_state_schedule(on_enter(&next), world, delta_time, time)?;
+    }
+    Ok(())
+}
@@ -1,17 +1,88 @@
-use std::collections::{HashMap, HashSet};
+use std::any::TypeId;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use crate::world::World;
-use crate::error::{SystemErrorContext, SystemErrorHandler, SystemErrorStrategy, default_error_handler};
-use std::sync::{Arc, Mutex};
+use crate::component::ComponentId;
+use crate::condition::RunCondition;
+use crate::profiling::{self, PhaseProfileEntry, ProfileReport, SystemProfileEntry, TimingSummary, TimingWindow};
+use crate::state::{self, NextState, State, States};
+use crate::system_param::{FunctionSystem, SystemAccess, SystemParamFunction};
+use crate::error::{SystemErrorContext, SystemErrorHandler, SystemErrorStrategy, TX2Error, default_error_handler};
+use serde::Serialize;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 
 pub type SystemId = String;
+pub type SystemSetId = String;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+type StateDriver = fn(&mut SystemScheduler, &mut World, f64, f64) -> Result<(), TX2Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum SystemPhase {
     Init,
     FixedUpdate,
     Update,
     LateUpdate,
     Cleanup,
+    // Application-defined phase or schedule; must be registered with the scheduler.
+    Custom(&'static str),
+}
+
+impl SystemPhase {
+    pub const BUILT_IN: [SystemPhase; 5] = [
+        SystemPhase::Init,
+        SystemPhase::FixedUpdate,
+        SystemPhase::Update,
+        SystemPhase::LateUpdate,
+        SystemPhase::Cleanup,
+    ];
+
+    // Builds a custom label from a runtime string. Labels are interned, so each
+    // distinct name is allocated once for the lifetime of the process.
+    pub fn custom(name: &str) -> SystemPhase {
+        static LABELS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+        let mut labels = LABELS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(label) = labels.get(name) {
+            return SystemPhase::Custom(label);
+        }
+        let label: &'static str = Box::leak(name.to_string().into_boxed_str());
+        labels.insert(label);
+        SystemPhase::Custom(label)
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, SystemPhase::Custom(_))
+    }
+}
+
+// Where a custom phase runs within the frame, relative to another phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseAnchor {
+    Before(SystemPhase),
+    After(SystemPhase),
+}
+
+impl PhaseAnchor {
+    pub fn target(&self) -> SystemPhase {
+        match self {
+            PhaseAnchor::Before(phase) | PhaseAnchor::After(phase) => *phase,
+        }
+    }
+}
+
+impl fmt::Display for SystemPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SystemPhase::Init => "init",
+            SystemPhase::FixedUpdate => "fixedUpdate",
+            SystemPhase::Update => "update",
+            SystemPhase::LateUpdate => "lateUpdate",
+            SystemPhase::Cleanup => "cleanup",
+            SystemPhase::Custom(label) => label,
+        };
+        f.write_str(name)
+    }
 }
 
 pub struct SystemContext<'a> {
@@ -63,6 +134,154 @@ impl SystemFnWrapper {
     // Let's change SystemFn to always return Result, and provide a helper for infallible systems.
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemRunStatus {
+    Completed,
+    Failed,
+    Skipped,
+    Disabled,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SystemStats {
+    pub run_count: u64,
+    pub failure_count: u64,
+    pub skip_count: u64,
+    pub timings: TimingWindow,
+}
+
+impl SystemStats {
+    fn with_window(window: usize) -> Self {
+        Self {
+            timings: TimingWindow::new(window),
+            ..Default::default()
+        }
+    }
+
+    pub fn last_duration_ms(&self) -> f64 {
+        self.timings.last().unwrap_or(0.0)
+    }
+
+    pub fn summary(&self) -> TimingSummary {
+        self.timings.summary()
+    }
+}
+
+// Groups systems under a shared priority, ordering constraints and enable flag.
+// Constraints may name systems or other sets; sets nest through `parent`.
+pub struct SystemSet {
+    pub id: SystemSetId,
+    pub priority: i32,
+    pub run_before: HashSet<String>,
+    pub run_after: HashSet<String>,
+    pub enabled: bool,
+    pub parent: Option<SystemSetId>,
+}
+
+impl SystemSet {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            priority: 0,
+            run_before: HashSet::new(),
+            run_after: HashSet::new(),
+            enabled: true,
+            parent: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn before(mut self, id: &str) -> Self {
+        self.run_before.insert(id.to_string());
+        self
+    }
+
+    pub fn after(mut self, id: &str) -> Self {
+        self.run_after.insert(id.to_string());
+        self
+    }
+
+    pub fn in_set(mut self, parent: &str) -> Self {
+        self.parent = Some(parent.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemCommand {
+    Enable(SystemId),
+    Disable(SystemId),
+    SetPriority(SystemId, i32),
+    ReenableFailed,
+}
+
+// Scheduler changes requested from inside running systems. Queued on the world
+// and applied by the scheduler between system runs.
+#[derive(Debug, Default)]
+pub struct SystemCommands {
+    queue: Vec<SystemCommand>,
+}
+
+impl SystemCommands {
+    pub fn enable(&mut self, system_id: &str) {
+        self.queue.push(SystemCommand::Enable(system_id.to_string()));
+    }
+
+    pub fn disable(&mut self, system_id: &str) {
+        self.queue.push(SystemCommand::Disable(system_id.to_string()));
+    }
+
+    pub fn set_priority(&mut self, system_id: &str, priority: i32) {
+        self.queue.push(SystemCommand::SetPriority(system_id.to_string(), priority));
+    }
+
+    pub fn reenable_failed(&mut self) {
+        self.queue.push(SystemCommand::ReenableFailed);
+    }
+
+    pub fn push(&mut self, command: SystemCommand) {
+        self.queue.push(command);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<SystemCommand> {
+        std::mem::take(&mut self.queue)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub id: SystemId,
+    pub name: String,
+    pub phases: Vec<String>,
+    pub priority: i32,
+    pub enabled: bool,
+    pub disabled_by_error: bool,
+    pub consecutive_failures: u32,
+    pub set: Option<SystemSetId>,
+    pub run_before: Vec<SystemId>,
+    pub run_after: Vec<SystemId>,
+    pub reads: Vec<ComponentId>,
+    pub writes: Vec<ComponentId>,
+}
+
+// Two systems touch the same component, at least one of them mutably, and
+// nothing orders them relative to each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemAmbiguity {
+    pub phase: SystemPhase,
+    pub first: SystemId,
+    pub second: SystemId,
+    pub conflicts: Vec<ComponentId>,
+}
+
 pub struct System {
     pub id: SystemId,
     pub name: String,
@@ -71,8 +290,17 @@ pub struct System {
     pub run_before: HashSet<SystemId>,
     pub run_after: HashSet<SystemId>,
     pub enabled: bool,
+    pub disabled_by_error: bool,
     pub consecutive_failures: u32,
     pub on_error: Option<SystemErrorHandler>,
+    pub run_conditions: Vec<RunCondition>,
+    pub set: Option<SystemSetId>,
+    pub reads: HashSet<ComponentId>,
+    pub writes: HashSet<ComponentId>,
+    pub max_retries: u32,
+    pub retry_backoff_frames: u32,
+    // Skipped until the world reaches this frame.
+    backoff_until_frame: u64,
     fn_ptr: Box<dyn SystemFn>,
 }
 
@@ -94,65 +322,254 @@ impl System {
             run_before,
             run_after,
             enabled: true,
+            disabled_by_error: false,
             consecutive_failures: 0,
             on_error: None,
+            run_conditions: Vec::new(),
+            set: None,
+            reads: HashSet::new(),
+            writes: HashSet::new(),
+            max_retries: 1,
+            retry_backoff_frames: 0,
+            backoff_until_frame: 0,
             fn_ptr: func,
         }
     }
 
+    // Builds a system from a plain function whose parameters (queries,
+    // resources, events, commands, locals) are fetched on every run. The
+    // parameter types determine the system's reads and writes.
+    pub fn from_fn<M: 'static, F: SystemParamFunction<M>>(id: &str, phases: HashSet<SystemPhase>, func: F) -> Self {
+        let mut access = SystemAccess::default();
+        let function = FunctionSystem::new(func, &mut access);
+        if !access.conflicts().is_empty() {
+            panic!("System {} has conflicting parameters: {}", id, access.conflicts().join(", "));
+        }
+
+        let mut system = System::new(
+            id.to_string(),
+            id.to_string(),
+            phases,
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(function),
+        );
+        system.reads = access.reads;
+        system.writes = access.writes;
+        system
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn before(mut self, id: &str) -> Self {
+        self.run_before.insert(id.to_string());
+        self
+    }
+
+    pub fn after(mut self, id: &str) -> Self {
+        self.run_after.insert(id.to_string());
+        self
+    }
+
     pub fn with_error_handler(mut self, handler: SystemErrorHandler) -> Self {
         self.on_error = Some(handler);
         self
     }
 
-    pub fn run(&mut self, ctx: SystemContext) {
+    pub fn with_run_condition(mut self, condition: RunCondition) -> Self {
+        self.run_conditions.push(condition);
+        self
+    }
+
+    pub fn in_set(mut self, set: &str) -> Self {
+        self.set = Some(set.to_string());
+        self
+    }
+
+    pub fn with_reads(mut self, components: Vec<ComponentId>) -> Self {
+        self.reads.extend(components);
+        self
+    }
+
+    pub fn with_writes(mut self, components: Vec<ComponentId>) -> Self {
+        self.writes.extend(components);
+        self
+    }
+
+    // Used when the error handler answers `Retry`: the system is re-run up to
+    // `max_retries` times within the frame, then skipped for the rest of that
+    // frame and the next `backoff_frames` frames, however often it is scheduled.
+    pub fn with_retry(mut self, max_retries: u32, backoff_frames: u32) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff_frames = backoff_frames;
+        self
+    }
+
+    pub fn info(&self) -> SystemInfo {
+        let mut phases: Vec<SystemPhase> = self.phases.iter().copied().collect();
+        phases.sort();
+        let mut run_before: Vec<SystemId> = self.run_before.iter().cloned().collect();
+        run_before.sort();
+        let mut run_after: Vec<SystemId> = self.run_after.iter().cloned().collect();
+        run_after.sort();
+        let mut reads: Vec<ComponentId> = self.reads.iter().cloned().collect();
+        reads.sort();
+        let mut writes: Vec<ComponentId> = self.writes.iter().cloned().collect();
+        writes.sort();
+
+        SystemInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            phases: phases.iter().map(|p| p.to_string()).collect(),
+            priority: self.priority,
+            enabled: self.enabled,
+            disabled_by_error: self.disabled_by_error,
+            consecutive_failures: self.consecutive_failures,
+            set: self.set.clone(),
+            run_before,
+            run_after,
+            reads,
+            writes,
+        }
+    }
+
+    pub fn should_run(&self, world: &World) -> bool {
+        self.run_conditions.iter().all(|c| c.evaluate(world))
+    }
+
+    pub fn run(&mut self, ctx: SystemContext) -> SystemRunStatus {
+        self.run_with(ctx, false)
+    }
+
+    // Like `run`, but a panic inside the system is caught and reported to the
+    // error handler as a failure instead of unwinding into the caller.
+    pub fn run_isolated(&mut self, ctx: SystemContext) -> SystemRunStatus {
+        self.run_with(ctx, true)
+    }
+
+    fn run_with(&mut self, ctx: SystemContext, catch_panics: bool) -> SystemRunStatus {
         if !self.enabled {
-            return;
+            return SystemRunStatus::Disabled;
         }
 
-        match self.fn_ptr.run(ctx) {
-            Ok(_) => {
-                self.consecutive_failures = 0;
-            }
-            Err(e) => {
-                self.consecutive_failures += 1;
-                let phase_str = match self.phases.iter().next().unwrap_or(&SystemPhase::Update) {
-                    SystemPhase::Init => "init",
-                    SystemPhase::FixedUpdate => "fixedUpdate",
-                    SystemPhase::Update => "update",
-                    SystemPhase::LateUpdate => "lateUpdate",
-                    SystemPhase::Cleanup => "cleanup",
-                }.to_string(); // Approximate phase for now
-
-                let error_ctx = SystemErrorContext {
-                    system_id: self.id.clone(),
-                    error: e,
-                    phase: phase_str,
-                    consecutive_failures: self.consecutive_failures,
-                };
+        if !self.should_run(ctx.world) {
+            return SystemRunStatus::Skipped;
+        }
 
-                let strategy = if let Some(handler) = self.on_error {
-                    handler(&error_ctx)
-                } else {
-                    default_error_handler(&error_ctx)
-                };
+        let frame = ctx.world.frame_count();
+        if frame < self.backoff_until_frame {
+            return SystemRunStatus::Skipped;
+        }
+
+        let SystemContext { world, delta_time, time, phase } = ctx;
+        let mut attempt = 0;
 
-                match strategy {
-                    SystemErrorStrategy::Disable => self.enabled = false,
-                    SystemErrorStrategy::Ignore => {},
-                    SystemErrorStrategy::Retry => {
-                        // Retry logic would go here, but requires re-running the function immediately
-                        // which is tricky with ownership. For now, we treat Retry as Ignore.
+        loop {
+            let ctx = SystemContext {
+                world: &mut *world,
+                delta_time,
+                time,
+                phase,
+            };
+            let result = if catch_panics {
+                match panic::catch_unwind(AssertUnwindSafe(|| self.fn_ptr.run(ctx))) {
+                    Ok(result) => result.map_err(|e| (e, false)),
+                    Err(payload) => Err((panic_message(payload.as_ref()), true)),
+                }
+            } else {
+                self.fn_ptr.run(ctx).map_err(|e| (e, false))
+            };
+            let (error, panicked) = match result {
+                Ok(_) => {
+                    self.consecutive_failures = 0;
+                    return SystemRunStatus::Completed;
+                }
+                Err(e) => e,
+            };
+
+            self.consecutive_failures += 1;
+            let error_ctx = SystemErrorContext {
+                system_id: self.id.clone(),
+                error,
+                phase,
+                consecutive_failures: self.consecutive_failures,
+                panicked,
+                attempt,
+                frame,
+                elapsed_time: time,
+            };
+
+            let strategy = if let Some(handler) = self.on_error {
+                handler(&error_ctx)
+            } else {
+                default_error_handler(&error_ctx)
+            };
+
+            match strategy {
+                SystemErrorStrategy::Disable => {
+                    self.enabled = false;
+                    self.disabled_by_error = true;
+                }
+                SystemErrorStrategy::Ignore => {}
+                SystemErrorStrategy::Retry => {
+                    if attempt < self.max_retries {
+                        attempt += 1;
+                        continue;
+                    }
+                    if self.retry_backoff_frames > 0 {
+                        self.backoff_until_frame = frame + self.retry_backoff_frames as u64 + 1;
                     }
                 }
             }
+            return SystemRunStatus::Failed;
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+// A system that panicked while its lock was held leaves the mutex poisoned;
+// its state is still usable, so recover it instead of propagating the panic.
+fn lock_system(system: &Mutex<System>) -> MutexGuard<'_, System> {
+    match system.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            system.clear_poison();
+            poisoned.into_inner()
         }
     }
 }
 
 pub struct SystemScheduler {
     systems: HashMap<SystemId, Arc<Mutex<System>>>,
+    sets: HashMap<SystemSetId, SystemSet>,
     execution_order: HashMap<SystemPhase, Vec<SystemId>>,
+    stats: HashMap<(SystemId, SystemPhase), SystemStats>,
+    phase_stats: HashMap<SystemPhase, SystemStats>,
+    profile_window: usize,
+    initialized: HashSet<SystemId>,
+    ambiguities: Vec<SystemAmbiguity>,
+    // Registered custom labels in registration order; `None` marks an on-demand schedule.
+    custom_phases: Vec<(SystemPhase, Option<PhaseAnchor>)>,
+    state_drivers: Vec<(TypeId, StateDriver)>,
+    catch_panics: bool,
     dirty: bool,
 }
 
@@ -160,7 +577,16 @@ impl SystemScheduler {
     pub fn new() -> Self {
         Self {
             systems: HashMap::new(),
+            sets: HashMap::new(),
             execution_order: HashMap::new(),
+            stats: HashMap::new(),
+            phase_stats: HashMap::new(),
+            profile_window: profiling::DEFAULT_PROFILE_WINDOW,
+            initialized: HashSet::new(),
+            ambiguities: Vec::new(),
+            custom_phases: Vec::new(),
+            state_drivers: Vec::new(),
+            catch_panics: false,
             dirty: true,
         }
     }
@@ -175,114 +601,707 @@ impl SystemScheduler {
 
     pub fn remove(&mut self, system_id: &str) -> bool {
         if self.systems.remove(system_id).is_some() {
+            self.stats.retain(|(id, _), _| id != system_id);
+            self.initialized.remove(system_id);
             self.dirty = true;
             return true;
         }
         false
     }
 
-    pub fn execute_phase(&mut self, phase: SystemPhase, world: &mut World, delta_time: f64, time: f64) {
+    // Enabling a system also clears its failure count, so systems disabled by
+    // the error handler get a fresh start.
+    pub fn enable(&mut self, system_id: &str) -> bool {
+        self.with_system(system_id, |system| {
+            system.enabled = true;
+            system.disabled_by_error = false;
+            system.consecutive_failures = 0;
+        })
+    }
+
+    pub fn disable(&mut self, system_id: &str) -> bool {
+        self.with_system(system_id, |system| system.enabled = false)
+    }
+
+    pub fn is_enabled(&self, system_id: &str) -> bool {
+        self.systems.get(system_id).is_some_and(|s| lock_system(s).enabled)
+    }
+
+    pub fn set_priority(&mut self, system_id: &str, priority: i32) -> bool {
+        let found = self.with_system(system_id, |system| system.priority = priority);
+        if found {
+            self.dirty = true;
+        }
+        found
+    }
+
+    // Re-enables every system that was disabled by its error handler.
+    pub fn reenable_failed_systems(&mut self) -> Vec<SystemId> {
+        let mut reenabled = Vec::new();
+        for (id, system_arc) in &self.systems {
+            let mut system = lock_system(system_arc);
+            if system.disabled_by_error {
+                system.enabled = true;
+                system.disabled_by_error = false;
+                system.consecutive_failures = 0;
+                reenabled.push(id.clone());
+            }
+        }
+        reenabled.sort();
+        reenabled
+    }
+
+    pub fn list_systems(&self) -> Vec<SystemInfo> {
+        let mut systems: Vec<SystemInfo> = self.systems.values()
+            .map(|s| lock_system(s).info())
+            .collect();
+        systems.sort_by(|a, b| a.id.cmp(&b.id));
+        systems
+    }
+
+    pub fn get_system_info(&self, system_id: &str) -> Option<SystemInfo> {
+        self.systems.get(system_id).map(|s| lock_system(s).info())
+    }
+
+    pub fn apply_command(&mut self, command: SystemCommand) -> bool {
+        match command {
+            SystemCommand::Enable(id) => self.enable(&id),
+            SystemCommand::Disable(id) => self.disable(&id),
+            SystemCommand::SetPriority(id, priority) => self.set_priority(&id, priority),
+            SystemCommand::ReenableFailed => {
+                self.reenable_failed_systems();
+                true
+            }
+        }
+    }
+
+    fn apply_commands(&mut self, world: &mut World) {
+        for command in world.system_commands().drain() {
+            if !self.apply_command(command.clone()) {
+                log::warn!("Ignoring system command for unknown system: {:?}", command);
+            }
+        }
+    }
+
+    fn with_system(&mut self, system_id: &str, f: impl FnOnce(&mut System)) -> bool {
+        match self.systems.get(system_id) {
+            Some(system_arc) => {
+                f(&mut lock_system(system_arc));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // When enabled, a panicking system is reported through its error handler
+    // like any other failure and the rest of the frame keeps running.
+    pub fn set_panic_isolation(&mut self, enabled: bool) {
+        self.catch_panics = enabled;
+    }
+
+    pub fn panic_isolation(&self) -> bool {
+        self.catch_panics
+    }
+
+    pub fn add_set(&mut self, set: SystemSet) {
+        if self.sets.contains_key(&set.id) {
+            panic!("System set {} already exists", set.id);
+        }
+        self.sets.insert(set.id.clone(), set);
+        self.dirty = true;
+    }
+
+    pub fn remove_set(&mut self, set_id: &str) -> bool {
+        if self.sets.remove(set_id).is_some() {
+            self.dirty = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn set_set_enabled(&mut self, set_id: &str, enabled: bool) -> bool {
+        if let Some(set) = self.sets.get_mut(set_id) {
+            set.enabled = enabled;
+            return true;
+        }
+        false
+    }
+
+    // A set is only effectively enabled when all of its ancestors are.
+    pub fn is_set_enabled(&self, set_id: &str) -> bool {
+        self.set_chain(Some(set_id))
+            .iter()
+            .all(|id| self.sets.get(id).is_none_or(|s| s.enabled))
+    }
+
+    // Returns the set ids from the innermost set outwards, stopping on unknown ids or loops.
+    fn set_chain(&self, start: Option<&str>) -> Vec<SystemSetId> {
+        let mut chain: Vec<SystemSetId> = Vec::new();
+        let mut current = start.map(|s| s.to_string());
+        while let Some(id) = current {
+            if chain.contains(&id) {
+                break;
+            }
+            current = self.sets.get(&id).and_then(|s| s.parent.clone());
+            chain.push(id);
+        }
+        chain
+    }
+
+    // Registers a custom phase that runs every time its anchor does, e.g.
+    // `PrePhysics` before `FixedUpdate`. Anchors may be FixedUpdate, Update,
+    // LateUpdate or another anchored custom phase.
+    pub fn register_phase(&mut self, phase: SystemPhase, anchor: PhaseAnchor) {
+        self.check_new_label(phase);
+        let target = anchor.target();
+        let anchorable = matches!(target, SystemPhase::FixedUpdate | SystemPhase::Update | SystemPhase::LateUpdate)
+            || self.custom_phases.iter().any(|(p, a)| *p == target && a.is_some());
+        if !anchorable {
+            panic!("Phase {} cannot be anchored to {}", phase, target);
+        }
+        self.custom_phases.push((phase, Some(anchor)));
+        self.dirty = true;
+    }
+
+    // Registers a named schedule that only runs when asked to through `run_schedule`.
+    pub fn register_schedule(&mut self, schedule: SystemPhase) {
+        self.check_new_label(schedule);
+        self.custom_phases.push((schedule, None));
+        self.dirty = true;
+    }
+
+    pub fn is_registered(&self, phase: SystemPhase) -> bool {
+        !phase.is_custom() || self.custom_phases.iter().any(|(p, _)| *p == phase)
+    }
+
+    fn check_new_label(&self, phase: SystemPhase) {
+        if !phase.is_custom() {
+            panic!("Phase {} is built in and cannot be registered", phase);
+        }
+        if self.is_registered(phase) {
+            panic!("Phase {} already exists", phase);
+        }
+    }
+
+    // The phase followed by everything anchored to it, in frame order.
+    pub fn phase_group(&self, phase: SystemPhase) -> Vec<SystemPhase> {
+        let mut group = Vec::new();
+        for (custom, anchor) in &self.custom_phases {
+            if *anchor == Some(PhaseAnchor::Before(phase)) {
+                group.extend(self.phase_group(*custom));
+            }
+        }
+        group.push(phase);
+        for (custom, anchor) in &self.custom_phases {
+            if *anchor == Some(PhaseAnchor::After(phase)) {
+                group.extend(self.phase_group(*custom));
+            }
+        }
+        group
+    }
+
+    // Executes a phase together with the custom phases anchored around it.
+    pub fn execute_phase_group(
+        &mut self,
+        phase: SystemPhase,
+        world: &mut World,
+        delta_time: f64,
+        time: f64,
+    ) -> Result<(), TX2Error> {
+        for member in self.phase_group(phase) {
+            self.execute_phase(member, world, delta_time, time)?;
+        }
+        Ok(())
+    }
+
+    pub fn run_schedule(
+        &mut self,
+        schedule: SystemPhase,
+        world: &mut World,
+        delta_time: f64,
+        time: f64,
+    ) -> Result<(), TX2Error> {
+        self.execute_phase(schedule, world, delta_time, time)
+    }
+
+    // Installs a state machine: inserts `State<S>` and `NextState<S>` into the
+    // world and applies queued transitions at the start of every frame,
+    // running the OnExit schedule of the old state and the OnEnter schedule
+    // of the new one.
+    pub fn add_state<S: States>(&mut self, world: &mut World, initial: S) {
+        if self.state_drivers.iter().any(|(id, _)| *id == TypeId::of::<S>()) {
+            panic!("State {} already exists", std::any::type_name::<S>());
+        }
+        world.insert_resource(State::new(initial));
+        world.insert_resource(NextState::<S>::default());
+        self.state_drivers.push((TypeId::of::<S>(), state::apply_transitions::<S>));
+    }
+
+    pub fn apply_state_transitions(&mut self, world: &mut World, delta_time: f64, time: f64) -> Result<(), TX2Error> {
+        let drivers: Vec<StateDriver> = self.state_drivers.iter().map(|(_, driver)| *driver).collect();
+        for driver in drivers {
+            driver(self, world, delta_time, time)?;
+        }
+        Ok(())
+    }
+
+    // OnEnter/OnExit schedules are registered on first use.
+    pub(crate) fn run_state_schedule(
+        &mut self,
+        schedule: SystemPhase,
+        world: &mut World,
+        delta_time: f64,
+        time: f64,
+    ) -> Result<(), TX2Error> {
+        if !self.is_registered(schedule) {
+            self.register_schedule(schedule);
+        }
+        self.run_schedule(schedule, world, delta_time, time)
+    }
+
+    pub fn execute_phase(
+        &mut self,
+        phase: SystemPhase,
+        world: &mut World,
+        delta_time: f64,
+        time: f64,
+    ) -> Result<(), TX2Error> {
+        if !self.is_registered(phase) {
+            return Err(TX2Error::UnknownPhase { phase: phase.to_string() });
+        }
+        self.apply_commands(world);
+        if self.dirty {
+            self.recompute_execution_order()?;
+        }
+
+        let start = profiling::now_ms();
+        match phase {
+            SystemPhase::Init => self.initialize_pending(world, time),
+            SystemPhase::Cleanup => self.run_cleanup(world, delta_time, time),
+            _ => {
+                // Systems added since the last frame are initialized before their first update.
+                self.initialize_pending(world, time);
+                let ids = self.execution_order.get(&phase).cloned().unwrap_or_default();
+                self.run_systems(&ids, phase, world, delta_time, time);
+            }
+        }
+
+        let window = self.profile_window;
+        let stats = self.phase_stats.entry(phase).or_insert_with(|| SystemStats::with_window(window));
+        stats.run_count += 1;
+        stats.timings.record(profiling::now_ms() - start);
+        Ok(())
+    }
+
+    // Runs Cleanup for every initialized system in reverse execution order.
+    // Systems are initialized again if the scheduler is used afterwards.
+    pub fn shutdown(&mut self, world: &mut World) -> Result<(), TX2Error> {
         if self.dirty {
-            self.recompute_execution_order();
-        }
-
-        if let Some(system_ids) = self.execution_order.get(&phase) {
-            let ids = system_ids.clone(); 
-            
-            for system_id in ids {
-                if let Some(system_arc) = self.systems.get(&system_id) {
-                    let mut system = system_arc.lock().unwrap();
-                    let ctx = SystemContext {
-                        world,
-                        delta_time,
-                        time,
-                        phase,
-                    };
-                    system.run(ctx);
+            self.recompute_execution_order()?;
+        }
+        let time = world.time();
+        self.run_cleanup(world, 0.0, time);
+        Ok(())
+    }
+
+    pub fn is_initialized(&self, system_id: &str) -> bool {
+        self.initialized.contains(system_id)
+    }
+
+    fn initialize_pending(&mut self, world: &mut World, time: f64) {
+        if self.initialized.len() == self.systems.len() {
+            return;
+        }
+
+        let with_init: HashSet<SystemId> = self.execution_order.get(&SystemPhase::Init)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+        let pending: Vec<SystemId> = self.execution_order.get(&SystemPhase::Init)
+            .map(|ids| ids.iter().filter(|id| !self.initialized.contains(*id)).cloned().collect())
+            .unwrap_or_default();
+        // Init that was disabled or skipped by a run condition is retried on
+        // the next frame; only a run, even a failed one, counts.
+        let ran = self.run_systems(&pending, SystemPhase::Init, world, 0.0, time);
+        self.initialized.extend(ran);
+
+        // Systems without an Init phase count as initialized as soon as they are scheduled.
+        let without_init: Vec<SystemId> = self.systems.keys()
+            .filter(|id| !with_init.contains(*id))
+            .cloned()
+            .collect();
+        self.initialized.extend(without_init);
+    }
+
+    fn run_cleanup(&mut self, world: &mut World, delta_time: f64, time: f64) {
+        let ids: Vec<SystemId> = self.execution_order.get(&SystemPhase::Cleanup)
+            .map(|ids| ids.iter().rev().filter(|id| self.initialized.contains(*id)).cloned().collect())
+            .unwrap_or_default();
+        self.run_systems(&ids, SystemPhase::Cleanup, world, delta_time, time);
+        self.initialized.clear();
+    }
+
+    // Runs the systems in order and returns those that actually ran.
+    fn run_systems(&mut self, ids: &[SystemId], phase: SystemPhase, world: &mut World, delta_time: f64, time: f64) -> Vec<SystemId> {
+        let mut ran = Vec::new();
+        for system_id in ids {
+            if let Some(system_arc) = self.systems.get(system_id) {
+                let mut system = lock_system(system_arc);
+                if let Some(set_id) = &system.set {
+                    if !self.is_set_enabled(set_id) {
+                        continue;
+                    }
+                }
+                let ctx = SystemContext {
+                    world,
+                    delta_time,
+                    time,
+                    phase,
+                };
+                let start = profiling::now_ms();
+                let status = if self.catch_panics {
+                    system.run_isolated(ctx)
+                } else {
+                    system.run(ctx)
+                };
+                let elapsed = profiling::now_ms() - start;
+                drop(system);
+                if !world.system_commands().is_empty() {
+                    self.apply_commands(world);
+                }
+
+                let window = self.profile_window;
+                let stats = self.stats.entry((system_id.clone(), phase))
+                    .or_insert_with(|| SystemStats::with_window(window));
+                if matches!(status, SystemRunStatus::Completed | SystemRunStatus::Failed) {
+                    stats.timings.record(elapsed);
+                }
+                match status {
+                    SystemRunStatus::Completed => stats.run_count += 1,
+                    SystemRunStatus::Failed => {
+                        stats.run_count += 1;
+                        stats.failure_count += 1;
+                    }
+                    SystemRunStatus::Skipped => stats.skip_count += 1,
+                    SystemRunStatus::Disabled => {}
+                }
+                if matches!(status, SystemRunStatus::Completed | SystemRunStatus::Failed) {
+                    ran.push(system_id.clone());
                 }
             }
         }
+        ran
     }
 
-    fn recompute_execution_order(&mut self) {
-        self.execution_order.clear();
-        let phases = [
-            SystemPhase::Init,
-            SystemPhase::FixedUpdate,
-            SystemPhase::Update,
-            SystemPhase::LateUpdate,
-            SystemPhase::Cleanup,
-        ];
+    pub fn stats(&self, system_id: &str, phase: SystemPhase) -> Option<&SystemStats> {
+        self.stats.get(&(system_id.to_string(), phase))
+    }
 
+    pub fn total_skipped(&self) -> u64 {
+        self.stats.values().map(|s| s.skip_count).sum()
+    }
+
+    pub fn phase_stats(&self, phase: SystemPhase) -> Option<&SystemStats> {
+        self.phase_stats.get(&phase)
+    }
+
+    // Number of samples kept for the rolling min/avg/max/p99 figures.
+    pub fn set_profile_window(&mut self, samples: usize) {
+        self.profile_window = samples.max(1);
+        for stats in self.stats.values_mut().chain(self.phase_stats.values_mut()) {
+            stats.timings.set_capacity(self.profile_window);
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.clear();
+        self.phase_stats.clear();
+    }
+
+    pub fn profile_report(&self) -> ProfileReport {
+        let mut systems: Vec<SystemProfileEntry> = self.stats.iter()
+            .map(|((system_id, phase), stats)| SystemProfileEntry {
+                system_id: system_id.clone(),
+                phase: phase.to_string(),
+                calls: stats.run_count,
+                failures: stats.failure_count,
+                skips: stats.skip_count,
+                last_ms: stats.last_duration_ms(),
+                timing: stats.summary(),
+            })
+            .collect();
+        systems.sort_by(|a, b| a.system_id.cmp(&b.system_id).then_with(|| a.phase.cmp(&b.phase)));
+
+        let mut phases: Vec<(&SystemPhase, &SystemStats)> = self.phase_stats.iter().collect();
+        phases.sort_by_key(|(phase, _)| **phase);
+        let phases = phases.into_iter()
+            .map(|(phase, stats)| PhaseProfileEntry {
+                phase: phase.to_string(),
+                calls: stats.run_count,
+                last_ms: stats.last_duration_ms(),
+                timing: stats.summary(),
+            })
+            .collect();
+
+        ProfileReport { systems, phases }
+    }
+
+    pub fn export_profile_json(&self) -> String {
+        self.profile_report().to_json()
+    }
+
+    // Rebuilds the per-phase execution order. Fails with the full cycle path if
+    // the ordering constraints of any phase cannot be satisfied.
+    pub fn recompute_execution_order(&mut self) -> Result<(), TX2Error> {
+        let phases = SystemPhase::BUILT_IN.into_iter()
+            .chain(self.custom_phases.iter().map(|(phase, _)| *phase));
+
+        let mut execution_order = HashMap::new();
+        let mut ambiguities = Vec::new();
         for phase in phases {
             let phase_systems: Vec<Arc<Mutex<System>>> = self.systems.values()
-                .filter(|s| s.lock().unwrap().phases.contains(&phase))
+                .filter(|s| lock_system(s).phases.contains(&phase))
                 .cloned()
                 .collect();
-            
-            let sorted = self.topological_sort(phase_systems);
-            self.execution_order.insert(phase, sorted);
+
+            let dependencies = self.resolve_constraints(&phase_systems);
+            let sorted = self.topological_sort(phase, &phase_systems, &dependencies)?;
+            ambiguities.extend(self.find_ambiguities(phase, &phase_systems, &sorted, &dependencies));
+            execution_order.insert(phase, sorted);
         }
 
+        for ambiguity in &ambiguities {
+            log::warn!(
+                "Systems {} and {} both access {:?} in phase {} without an ordering constraint",
+                ambiguity.first, ambiguity.second, ambiguity.conflicts, ambiguity.phase
+            );
+        }
+
+        self.execution_order = execution_order;
+        self.ambiguities = ambiguities;
         self.dirty = false;
+        Ok(())
     }
 
-    fn topological_sort(&self, systems: Vec<Arc<Mutex<System>>>) -> Vec<SystemId> {
-        let mut sorted = Vec::new();
-        let mut visited = HashSet::new();
-        let mut visiting = HashSet::new();
-        
-        let mut systems_by_priority = systems;
-        systems_by_priority.sort_by(|a, b| {
-            b.lock().unwrap().priority.cmp(&a.lock().unwrap().priority)
-        });
+    pub fn ambiguities(&self) -> &[SystemAmbiguity] {
+        &self.ambiguities
+    }
 
-        for system_arc in &systems_by_priority {
-            let system_id = system_arc.lock().unwrap().id.clone();
-            self.visit(&system_id, &mut visited, &mut visiting, &mut sorted);
+    // Expands a system or set id into the ids of the given systems it covers.
+    fn resolve_members(&self, id: &str, members: &HashMap<SystemId, Vec<SystemSetId>>) -> Vec<SystemId> {
+        if members.contains_key(id) {
+            return vec![id.to_string()];
         }
+        members.iter()
+            .filter(|(_, chain)| chain.iter().any(|set_id| set_id == id))
+            .map(|(system_id, _)| system_id.clone())
+            .collect()
+    }
+
+    // Collects every before/after constraint, from systems and from the sets
+    // they belong to, as "runs after" edges between systems of the same phase.
+    fn resolve_constraints(&self, systems: &[Arc<Mutex<System>>]) -> HashMap<SystemId, HashSet<SystemId>> {
+        let members: HashMap<SystemId, Vec<SystemSetId>> = systems.iter()
+            .map(|s| {
+                let system = lock_system(s);
+                (system.id.clone(), self.set_chain(system.set.as_deref()))
+            })
+            .collect();
+
+        let mut after: HashMap<SystemId, HashSet<SystemId>> = HashMap::new();
+        for system_arc in systems {
+            let system = lock_system(system_arc);
+            let mut before_ids: Vec<&String> = system.run_before.iter().collect();
+            let mut after_ids: Vec<&String> = system.run_after.iter().collect();
+            for set_id in &members[&system.id] {
+                if let Some(set) = self.sets.get(set_id) {
+                    before_ids.extend(set.run_before.iter());
+                    after_ids.extend(set.run_after.iter());
+                }
+            }
 
-        sorted
+            for id in after_ids {
+                for dependency in self.resolve_members(id, &members) {
+                    if dependency != system.id {
+                        after.entry(system.id.clone()).or_default().insert(dependency);
+                    }
+                }
+            }
+            for id in before_ids {
+                for dependent in self.resolve_members(id, &members) {
+                    if dependent != system.id {
+                        after.entry(dependent).or_default().insert(system.id.clone());
+                    }
+                }
+            }
+        }
+        after
     }
 
-    fn visit(
+    // Sort key: priorities from the outermost set down to the system itself.
+    fn priority_key(&self, system: &System) -> Vec<i32> {
+        let mut key: Vec<i32> = self.set_chain(system.set.as_deref())
+            .iter()
+            .rev()
+            .filter_map(|id| self.sets.get(id).map(|s| s.priority))
+            .collect();
+        key.push(system.priority);
+        key
+    }
+
+    // Kahn's algorithm. Among systems whose dependencies have all run, the one
+    // with the highest priority key goes first; ties break on id.
+    fn topological_sort(
         &self,
-        system_id: &SystemId,
-        visited: &mut HashSet<SystemId>,
-        visiting: &mut HashSet<SystemId>,
-        sorted: &mut Vec<SystemId>,
-    ) {
-        if visited.contains(system_id) {
-            return;
+        phase: SystemPhase,
+        systems: &[Arc<Mutex<System>>],
+        dependencies: &HashMap<SystemId, HashSet<SystemId>>,
+    ) -> Result<Vec<SystemId>, TX2Error> {
+        let keys: HashMap<SystemId, Vec<i32>> = systems.iter()
+            .map(|s| {
+                let system = lock_system(s);
+                (system.id.clone(), self.priority_key(&system))
+            })
+            .collect();
+
+        let mut in_degree: HashMap<&SystemId, usize> = keys.keys().map(|id| (id, 0)).collect();
+        let mut dependents: HashMap<&SystemId, Vec<&SystemId>> = HashMap::new();
+        for (system_id, deps) in dependencies {
+            for dep in deps {
+                *in_degree.get_mut(system_id).unwrap() += 1;
+                dependents.entry(dep).or_default().push(system_id);
+            }
         }
 
-        if visiting.contains(system_id) {
-            panic!("Circular dependency detected in system: {}", system_id);
+        let mut ready: BinaryHeap<(&Vec<i32>, Reverse<&SystemId>)> = in_degree.iter()
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(id, _)| (&keys[*id], Reverse(*id)))
+            .collect();
+
+        let mut sorted = Vec::with_capacity(keys.len());
+        while let Some((_, Reverse(system_id))) = ready.pop() {
+            sorted.push(system_id.clone());
+            for dependent in dependents.get(system_id).into_iter().flatten() {
+                let degree = in_degree.get_mut(*dependent).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push((&keys[*dependent], Reverse(*dependent)));
+                }
+            }
         }
 
-        visiting.insert(system_id.clone());
+        if sorted.len() < keys.len() {
+            let remaining: HashSet<&SystemId> = in_degree.iter()
+                .filter(|(_, degree)| **degree > 0)
+                .map(|(id, _)| *id)
+                .collect();
+            return Err(TX2Error::SystemCycle {
+                phase,
+                cycle: Self::find_cycle(&remaining, dependencies),
+            });
+        }
 
-        let system_arc = self.systems.get(system_id).unwrap();
-        let run_after = system_arc.lock().unwrap().run_after.clone();
+        Ok(sorted)
+    }
+
+    // Walks dependency edges among the unsorted systems until one repeats.
+    // Every unsorted system has a dependency that is itself unsorted, so this terminates on a cycle.
+    fn find_cycle(
+        remaining: &HashSet<&SystemId>,
+        dependencies: &HashMap<SystemId, HashSet<SystemId>>,
+    ) -> Vec<SystemId> {
+        let mut start: Vec<&SystemId> = remaining.iter().copied().collect();
+        start.sort();
+        let mut path: Vec<SystemId> = Vec::new();
+        let mut current = start[0].clone();
+        loop {
+            if let Some(pos) = path.iter().position(|id| *id == current) {
+                // The path runs from dependents to dependencies; report it in execution order.
+                let mut cycle: Vec<SystemId> = path[pos..].iter().rev().cloned().collect();
+                let min = cycle.iter().enumerate().min_by_key(|(_, id)| *id).map(|(i, _)| i).unwrap();
+                cycle.rotate_left(min);
+                cycle.push(cycle[0].clone());
+                return cycle;
+            }
+            let mut next: Vec<&SystemId> = dependencies[&current].iter()
+                .filter(|id| remaining.contains(id))
+                .collect();
+            next.sort();
+            path.push(current);
+            current = next[0].clone();
+        }
+    }
 
-        for after_id in run_after {
-            if self.systems.contains_key(&after_id) {
-                self.visit(&after_id, visited, visiting, sorted);
+    fn find_ambiguities(
+        &self,
+        phase: SystemPhase,
+        systems: &[Arc<Mutex<System>>],
+        sorted: &[SystemId],
+        dependencies: &HashMap<SystemId, HashSet<SystemId>>,
+    ) -> Vec<SystemAmbiguity> {
+        // Transitive dependencies, filled in execution order.
+        let mut ancestors: HashMap<&SystemId, HashSet<&SystemId>> = HashMap::new();
+        for system_id in sorted {
+            let mut all = HashSet::new();
+            for dep in dependencies.get(system_id).into_iter().flatten() {
+                all.insert(dep);
+                all.extend(ancestors[dep].iter().copied());
             }
+            ancestors.insert(system_id, all);
         }
 
-        let run_before = system_arc.lock().unwrap().run_before.clone();
-        for before_id in run_before {
-             if let Some(_) = self.systems.get(&before_id) {
-                 if !visited.contains(&before_id) {
-                     visiting.remove(system_id);
-                     return;
-                 }
-             }
+        let guards: Vec<_> = systems.iter().map(|s| lock_system(s)).collect();
+        let mut by_order: Vec<&System> = guards.iter().map(|g| &**g).collect();
+        by_order.sort_by_key(|s| sorted.iter().position(|id| *id == s.id));
+
+        let mut ambiguities = Vec::new();
+        for (i, first) in by_order.iter().enumerate() {
+            for second in &by_order[i + 1..] {
+                let mut conflicts: Vec<ComponentId> = first.writes.iter()
+                    .filter(|c| second.reads.contains(*c) || second.writes.contains(*c))
+                    .chain(second.writes.iter().filter(|c| first.reads.contains(*c)))
+                    .cloned()
+                    .collect();
+                if conflicts.is_empty()
+                    || ancestors[&second.id].contains(&first.id)
+                    || ancestors[&first.id].contains(&second.id)
+                {
+                    continue;
+                }
+                conflicts.sort();
+                conflicts.dedup();
+                ambiguities.push(SystemAmbiguity {
+                    phase,
+                    first: first.id.clone(),
+                    second: second.id.clone(),
+                    conflicts,
+                });
+            }
         }
+        ambiguities
+    }
+}
 
-        visiting.remove(system_id);
-        visited.insert(system_id.clone());
-        sorted.push(system_id.clone());
+// Best effort only: without a world to hand over, Cleanup runs against an
+// empty one, so it can release what systems own but sees no resources or
+// entities. Call `shutdown(&mut world)` for a Cleanup that sees the world.
+// Nothing runs while the thread is unwinding, where a panicking Cleanup
+// would abort the process.
+impl Drop for SystemScheduler {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        let pending_cleanup = self.systems.iter().any(|(id, system)| {
+            self.initialized.contains(id)
+                && lock_system(system).phases.contains(&SystemPhase::Cleanup)
+        });
+        if !pending_cleanup {
+            return;
+        }
+        log::warn!("SystemScheduler dropped without shutdown; running Cleanup against an empty world");
+        let mut world = World::new();
+        if let Err(e) = self.shutdown(&mut world) {
+            log::error!("Cleanup skipped: {}", e);
+        }
     }
 }
@@ -0,0 +1,813 @@
+use std::any::{Any, type_name};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use crate::component::{Component, ComponentId, ComponentStore};
+use crate::entity::{EntityId, create_entity_id};
+use crate::event::Events;
+use crate::query::QueryDescriptor;
+use crate::system::{SystemContext, SystemFn, SystemPhase};
+use crate::world::World;
+
+type ComponentMap = HashMap<ComponentId, Vec<Box<dyn Component>>>;
+type WorldOp = Box<dyn FnOnce(&mut World)>;
+
+// Components and resources a function system touches, collected from its
+// parameter types when the system is built.
+#[derive(Debug, Default, Clone)]
+pub struct SystemAccess {
+    pub reads: HashSet<ComponentId>,
+    pub writes: HashSet<ComponentId>,
+    // Writes applied only after the system returns; they alias nothing.
+    deferred: HashSet<ComponentId>,
+    conflicts: Vec<String>,
+}
+
+impl SystemAccess {
+    pub fn add_read(&mut self, id: ComponentId) {
+        if self.writes_directly(&id) {
+            self.conflicts.push(id.clone());
+        }
+        self.reads.insert(id);
+    }
+
+    pub fn add_write(&mut self, id: ComponentId) {
+        if self.reads.contains(&id) || self.writes_directly(&id) {
+            self.conflicts.push(id.clone());
+        }
+        self.deferred.remove(&id);
+        self.writes.insert(id);
+    }
+
+    // Still a write for ordering and ambiguity purposes, but it never
+    // conflicts with the system's other parameters.
+    pub fn add_deferred_write(&mut self, id: ComponentId) {
+        if !self.writes.contains(&id) {
+            self.deferred.insert(id.clone());
+        }
+        self.writes.insert(id);
+    }
+
+    // Resources are moved out of the world while a system runs, so a
+    // signature may only name each resource once.
+    pub fn add_resource(&mut self, id: ComponentId, write: bool) {
+        if self.reads.contains(&id) || self.writes_directly(&id) {
+            self.conflicts.push(id.clone());
+        }
+        if write {
+            self.deferred.remove(&id);
+            self.writes.insert(id);
+        } else {
+            self.reads.insert(id);
+        }
+    }
+
+    fn writes_directly(&self, id: &ComponentId) -> bool {
+        self.writes.contains(id) && !self.deferred.contains(id)
+    }
+
+    pub fn conflicts(&self) -> &[String] {
+        &self.conflicts
+    }
+}
+
+pub fn resource_access_id<R: ?Sized>() -> ComponentId {
+    format!("resource:{}", type_name::<R>())
+}
+
+// Per-run state shared by the parameters of one function system call. The
+// component store is moved in for the duration of the call; resources and
+// deferred commands are handed back to the world when the call ends.
+pub struct ParamScope {
+    store: RefCell<ComponentStore>,
+    restore: RefCell<Vec<WorldOp>>,
+    deferred: RefCell<Vec<WorldOp>>,
+    delta_time: f64,
+    time: f64,
+    phase: SystemPhase,
+}
+
+impl ParamScope {
+    pub fn restore<F: FnOnce(&mut World) + 'static>(&self, op: F) {
+        self.restore.borrow_mut().push(Box::new(op));
+    }
+
+    pub fn defer<F: FnOnce(&mut World) + 'static>(&self, op: F) {
+        self.deferred.borrow_mut().push(Box::new(op));
+    }
+
+    pub fn delta_time(&self) -> f64 {
+        self.delta_time
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn phase(&self) -> SystemPhase {
+        self.phase
+    }
+}
+
+// Puts the store back and applies returned resources, then deferred
+// commands, even if the system panics.
+struct ScopeGuard<'w> {
+    world: &'w mut World,
+    scope: Rc<ParamScope>,
+}
+
+impl Drop for ScopeGuard<'_> {
+    fn drop(&mut self) {
+        let store = self.scope.store.replace(ComponentStore::new());
+        self.world.component_store = store;
+        let restore: Vec<WorldOp> = self.scope.restore.borrow_mut().drain(..).collect();
+        for op in restore {
+            op(self.world);
+        }
+        let deferred: Vec<WorldOp> = self.scope.deferred.borrow_mut().drain(..).collect();
+        for op in deferred {
+            op(self.world);
+        }
+    }
+}
+
+pub trait SystemParam: Sized {
+    type State: Send + Sync + 'static;
+
+    fn init(access: &mut SystemAccess) -> Self::State;
+    fn fetch(state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String>;
+}
+
+pub trait QueryData {
+    type Item<'a>;
+
+    fn access(access: &mut SystemAccess);
+    fn components(ids: &mut Vec<ComponentId>);
+    // Components fetched mutably, in the order `fetch` consumes them.
+    fn writes(ids: &mut Vec<ComponentId>);
+    fn fetch<'a>(
+        components: &'a ComponentMap,
+        writes: &mut std::slice::IterMut<'a, Vec<Box<dyn Component>>>,
+    ) -> Option<Self::Item<'a>>;
+}
+
+impl<T: Component> QueryData for &T {
+    type Item<'a> = &'a T;
+
+    fn access(access: &mut SystemAccess) {
+        access.add_read(type_name::<T>().to_string());
+    }
+
+    fn components(ids: &mut Vec<ComponentId>) {
+        ids.push(type_name::<T>().to_string());
+    }
+
+    fn writes(_ids: &mut Vec<ComponentId>) {}
+
+    fn fetch<'a>(
+        components: &'a ComponentMap,
+        _writes: &mut std::slice::IterMut<'a, Vec<Box<dyn Component>>>,
+    ) -> Option<Self::Item<'a>> {
+        components.get(type_name::<T>())?.first()?.as_any().downcast_ref::<T>()
+    }
+}
+
+impl<T: Component> QueryData for &mut T {
+    type Item<'a> = &'a mut T;
+
+    fn access(access: &mut SystemAccess) {
+        access.add_write(type_name::<T>().to_string());
+    }
+
+    fn components(ids: &mut Vec<ComponentId>) {
+        ids.push(type_name::<T>().to_string());
+    }
+
+    fn writes(ids: &mut Vec<ComponentId>) {
+        ids.push(type_name::<T>().to_string());
+    }
+
+    fn fetch<'a>(
+        _components: &'a ComponentMap,
+        writes: &mut std::slice::IterMut<'a, Vec<Box<dyn Component>>>,
+    ) -> Option<Self::Item<'a>> {
+        writes.next()?.first_mut()?.as_any_mut().downcast_mut::<T>()
+    }
+}
+
+macro_rules! impl_query_data {
+    ($($data:ident),+) => {
+        impl<$($data: QueryData),+> QueryData for ($($data,)+) {
+            type Item<'a> = ($($data::Item<'a>,)+);
+
+            fn access(access: &mut SystemAccess) {
+                $($data::access(access);)+
+            }
+
+            fn components(ids: &mut Vec<ComponentId>) {
+                $($data::components(ids);)+
+            }
+
+            fn writes(ids: &mut Vec<ComponentId>) {
+                $($data::writes(ids);)+
+            }
+
+            fn fetch<'a>(
+                components: &'a ComponentMap,
+                writes: &mut std::slice::IterMut<'a, Vec<Box<dyn Component>>>,
+            ) -> Option<Self::Item<'a>> {
+                Some(($($data::fetch(components, writes)?,)+))
+            }
+        }
+    };
+}
+
+impl_query_data!(A);
+impl_query_data!(A, B);
+impl_query_data!(A, B, C);
+impl_query_data!(A, B, C, D);
+impl_query_data!(A, B, C, D, E);
+impl_query_data!(A, B, C, D, E, F);
+
+pub trait QueryFilterData: 'static {
+    fn filter(descriptor: &mut QueryDescriptor);
+}
+
+pub struct With<T>(PhantomData<T>);
+pub struct Without<T>(PhantomData<T>);
+
+impl QueryFilterData for () {
+    fn filter(_descriptor: &mut QueryDescriptor) {}
+}
+
+impl<T: Component> QueryFilterData for With<T> {
+    fn filter(descriptor: &mut QueryDescriptor) {
+        descriptor.all.push(type_name::<T>().to_string());
+    }
+}
+
+impl<T: Component> QueryFilterData for Without<T> {
+    fn filter(descriptor: &mut QueryDescriptor) {
+        descriptor.none.push(type_name::<T>().to_string());
+    }
+}
+
+macro_rules! impl_query_filter {
+    ($($filter:ident),+) => {
+        impl<$($filter: QueryFilterData),+> QueryFilterData for ($($filter,)+) {
+            fn filter(descriptor: &mut QueryDescriptor) {
+                $($filter::filter(descriptor);)+
+            }
+        }
+    };
+}
+
+impl_query_filter!(A);
+impl_query_filter!(A, B);
+impl_query_filter!(A, B, C);
+impl_query_filter!(A, B, C, D);
+
+// Typed view over entities with every component in `Q` that also pass `F`.
+// Queries without `&mut` components may be nested; a query that writes
+// borrows the store exclusively while it iterates.
+pub struct Query<Q, F = ()> {
+    scope: Rc<ParamScope>,
+    _marker: PhantomData<fn() -> (Q, F)>,
+}
+
+impl<Q: QueryData, F: QueryFilterData> Query<Q, F> {
+    pub fn entities(&self) -> Vec<EntityId> {
+        let mut descriptor = QueryDescriptor::default();
+        Q::components(&mut descriptor.all);
+        F::filter(&mut descriptor);
+        let store = self.scope.store.borrow();
+        let mut entities: Vec<EntityId> = crate::query::Query::new(descriptor)
+            .execute(&store)
+            .into_iter()
+            .collect();
+        entities.sort();
+        entities
+    }
+
+    pub fn count(&self) -> usize {
+        self.entities().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    pub fn for_each<G>(&mut self, mut f: G)
+    where
+        G: FnMut(EntityId, Q::Item<'_>),
+    {
+        for entity in self.entities() {
+            self.fetch_entity(entity, |item| f(entity, item));
+        }
+    }
+
+    pub fn get<R, G>(&mut self, entity: EntityId, f: G) -> Option<R>
+    where
+        G: FnOnce(Q::Item<'_>) -> R,
+    {
+        if !self.entities().contains(&entity) {
+            return None;
+        }
+        self.fetch_entity(entity, f)
+    }
+
+    fn fetch_entity<R, G>(&self, entity: EntityId, f: G) -> Option<R>
+    where
+        G: FnOnce(Q::Item<'_>) -> R,
+    {
+        let mut write_ids = Vec::new();
+        Q::writes(&mut write_ids);
+
+        if write_ids.is_empty() {
+            let store = self.scope.store.borrow();
+            let components = store.entity_components(entity)?;
+            let item = Q::fetch(components, &mut [].iter_mut())?;
+            return Some(f(item));
+        }
+
+        let Ok(mut store) = self.scope.store.try_borrow_mut() else {
+            panic!("Query writing {} cannot run while another query is iterating", write_ids.join(", "));
+        };
+        let components = store.take_entity_components(entity)?;
+        let mut split = SplitEntity {
+            store: &mut store,
+            entity,
+            write_ids,
+            components,
+            taken: Vec::new(),
+        };
+        for id in &split.write_ids {
+            split.taken.push(split.components.remove(id)?);
+        }
+        let item = Q::fetch(&split.components, &mut split.taken.iter_mut())?;
+        Some(f(item))
+    }
+}
+
+// An entity's components split into shared and mutably borrowed parts;
+// reassembled on drop so a panicking system cannot lose components.
+struct SplitEntity<'s> {
+    store: &'s mut ComponentStore,
+    entity: EntityId,
+    write_ids: Vec<ComponentId>,
+    components: ComponentMap,
+    taken: Vec<Vec<Box<dyn Component>>>,
+}
+
+impl Drop for SplitEntity<'_> {
+    fn drop(&mut self) {
+        let mut components = std::mem::take(&mut self.components);
+        for (id, list) in self.write_ids.iter().zip(self.taken.drain(..)) {
+            components.insert(id.clone(), list);
+        }
+        self.store.restore_entity_components(self.entity, components);
+        for id in &self.write_ids {
+            self.store.mark_changed(self.entity, id);
+        }
+    }
+}
+
+impl<Q: QueryData, F: QueryFilterData> SystemParam for Query<Q, F> {
+    type State = ();
+
+    fn init(access: &mut SystemAccess) -> Self::State {
+        Q::access(access);
+    }
+
+    fn fetch(_state: &mut Self::State, _world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        Ok(Self {
+            scope: scope.clone(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+pub struct Res<R: Any + Send + Sync> {
+    value: Option<R>,
+    scope: Rc<ParamScope>,
+}
+
+pub struct ResMut<R: Any + Send + Sync> {
+    value: Option<R>,
+    scope: Rc<ParamScope>,
+}
+
+fn take_resource<R: Any + Send + Sync>(world: &mut World) -> Result<R, String> {
+    world.remove_resource::<R>()
+        .ok_or_else(|| format!("Resource {} does not exist", type_name::<R>()))
+}
+
+fn return_resource<R: Any + Send + Sync>(value: &mut Option<R>, scope: &ParamScope) {
+    if let Some(value) = value.take() {
+        scope.restore(move |world| {
+            world.insert_resource(value);
+        });
+    }
+}
+
+impl<R: Any + Send + Sync> Deref for Res<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value.as_ref().expect("resource already returned")
+    }
+}
+
+impl<R: Any + Send + Sync> Drop for Res<R> {
+    fn drop(&mut self) {
+        return_resource(&mut self.value, &self.scope);
+    }
+}
+
+impl<R: Any + Send + Sync> SystemParam for Res<R> {
+    type State = ();
+
+    fn init(access: &mut SystemAccess) -> Self::State {
+        access.add_resource(resource_access_id::<R>(), false);
+    }
+
+    fn fetch(_state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        Ok(Self {
+            value: Some(take_resource::<R>(world)?),
+            scope: scope.clone(),
+        })
+    }
+}
+
+impl<R: Any + Send + Sync> Deref for ResMut<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value.as_ref().expect("resource already returned")
+    }
+}
+
+impl<R: Any + Send + Sync> DerefMut for ResMut<R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.value.as_mut().expect("resource already returned")
+    }
+}
+
+impl<R: Any + Send + Sync> Drop for ResMut<R> {
+    fn drop(&mut self) {
+        return_resource(&mut self.value, &self.scope);
+    }
+}
+
+impl<R: Any + Send + Sync> SystemParam for ResMut<R> {
+    type State = ();
+
+    fn init(access: &mut SystemAccess) -> Self::State {
+        access.add_resource(resource_access_id::<R>(), true);
+    }
+
+    fn fetch(_state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        Ok(Self {
+            value: Some(take_resource::<R>(world)?),
+            scope: scope.clone(),
+        })
+    }
+}
+
+// State private to one system that persists between its runs.
+pub struct Local<T: Default + Send + 'static> {
+    value: T,
+    home: Arc<Mutex<T>>,
+}
+
+impl<T: Default + Send + 'static> Deref for Local<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Default + Send + 'static> DerefMut for Local<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Default + Send + 'static> Drop for Local<T> {
+    fn drop(&mut self) {
+        let mut home = self.home.lock().unwrap_or_else(|e| e.into_inner());
+        *home = std::mem::take(&mut self.value);
+    }
+}
+
+impl<T: Default + Send + 'static> SystemParam for Local<T> {
+    type State = Arc<Mutex<T>>;
+
+    fn init(_access: &mut SystemAccess) -> Self::State {
+        Arc::new(Mutex::new(T::default()))
+    }
+
+    fn fetch(state: &mut Self::State, _world: &mut World, _scope: &Rc<ParamScope>) -> Result<Self, String> {
+        let value = std::mem::take(&mut *state.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(Self {
+            value,
+            home: state.clone(),
+        })
+    }
+}
+
+// World changes recorded during a run and applied once the system returns.
+pub struct Commands {
+    queue: Vec<WorldOp>,
+    scope: Rc<ParamScope>,
+}
+
+impl Commands {
+    pub fn spawn(&mut self) -> EntityId {
+        let id = create_entity_id();
+        self.add(move |world| {
+            world.create_entity_with_id(id);
+        });
+        id
+    }
+
+    pub fn spawn_with(&mut self, components: Vec<Box<dyn Component>>) -> EntityId {
+        let id = self.spawn();
+        for component in components {
+            self.add(move |world| {
+                if world.has_entity(id) {
+                    world.add_component(id, component);
+                }
+            });
+        }
+        id
+    }
+
+    pub fn despawn(&mut self, entity: EntityId) {
+        self.add(move |world| {
+            world.destroy_entity(entity);
+        });
+    }
+
+    pub fn insert<T: Component>(&mut self, entity: EntityId, component: T) {
+        self.add(move |world| {
+            if world.has_entity(entity) {
+                world.add_component(entity, Box::new(component));
+            }
+        });
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: EntityId) {
+        self.add(move |world| {
+            world.remove_component(entity, type_name::<T>());
+        });
+    }
+
+    pub fn insert_resource<R: Any + Send + Sync>(&mut self, resource: R) {
+        self.add(move |world| {
+            world.insert_resource(resource);
+        });
+    }
+
+    pub fn remove_resource<R: Any + Send + Sync>(&mut self) {
+        self.add(|world| {
+            world.remove_resource::<R>();
+        });
+    }
+
+    pub fn add<F: FnOnce(&mut World) + 'static>(&mut self, op: F) {
+        self.queue.push(Box::new(op));
+    }
+}
+
+impl Drop for Commands {
+    fn drop(&mut self) {
+        let queue = std::mem::take(&mut self.queue);
+        self.scope.defer(move |world| {
+            for op in queue {
+                op(world);
+            }
+        });
+    }
+}
+
+impl SystemParam for Commands {
+    type State = ();
+
+    fn init(_access: &mut SystemAccess) -> Self::State {}
+
+    fn fetch(_state: &mut Self::State, _world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        Ok(Self {
+            queue: Vec::new(),
+            scope: scope.clone(),
+        })
+    }
+}
+
+fn check_events<E: Clone + Send + Sync + 'static>(world: &World) -> Result<(), String> {
+    if world.has_resource::<Events<E>>() {
+        Ok(())
+    } else {
+        Err(format!("Event type {} is not registered", type_name::<E>()))
+    }
+}
+
+// Events sent since this system last ran.
+pub struct EventReader<E: Clone + Send + Sync + 'static> {
+    events: Vec<E>,
+}
+
+impl<E: Clone + Send + Sync + 'static> EventReader<E> {
+    pub fn iter(&self) -> std::slice::Iter<'_, E> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl<E: Clone + Send + Sync + 'static> SystemParam for EventReader<E> {
+    // Id of the first event this system has not seen yet.
+    type State = u64;
+
+    fn init(access: &mut SystemAccess) -> Self::State {
+        access.add_read(resource_access_id::<Events<E>>());
+        0
+    }
+
+    fn fetch(state: &mut Self::State, world: &mut World, _scope: &Rc<ParamScope>) -> Result<Self, String> {
+        check_events::<E>(world)?;
+        let (events, cursor) = world.get_resource::<Events<E>>()
+            .map(|events| events.read_from(*state))
+            .unwrap_or_default();
+        *state = cursor;
+        Ok(Self { events })
+    }
+}
+
+pub struct EventWriter<E: Clone + Send + Sync + 'static> {
+    events: Vec<E>,
+    scope: Rc<ParamScope>,
+}
+
+impl<E: Clone + Send + Sync + 'static> EventWriter<E> {
+    pub fn send(&mut self, event: E) {
+        self.events.push(event);
+    }
+}
+
+impl<E: Clone + Send + Sync + 'static> Drop for EventWriter<E> {
+    fn drop(&mut self) {
+        let events = std::mem::take(&mut self.events);
+        self.scope.defer(move |world| {
+            if let Some(queue) = world.get_resource_mut::<Events<E>>() {
+                for event in events {
+                    queue.send(event);
+                }
+            }
+        });
+    }
+}
+
+impl<E: Clone + Send + Sync + 'static> SystemParam for EventWriter<E> {
+    type State = ();
+
+    // Sent events are queued when the system returns, so readers of the same
+    // event type in the same system do not conflict with the writer.
+    fn init(access: &mut SystemAccess) -> Self::State {
+        access.add_deferred_write(resource_access_id::<Events<E>>());
+    }
+
+    fn fetch(_state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        check_events::<E>(world)?;
+        Ok(Self {
+            events: Vec::new(),
+            scope: scope.clone(),
+        })
+    }
+}
+
+// Timing of the phase the system is running in.
+#[derive(Debug, Clone, Copy)]
+pub struct Time {
+    pub delta_time: f64,
+    pub time: f64,
+    pub phase: SystemPhase,
+}
+
+impl SystemParam for Time {
+    type State = ();
+
+    fn init(_access: &mut SystemAccess) -> Self::State {}
+
+    fn fetch(_state: &mut Self::State, _world: &mut World, scope: &Rc<ParamScope>) -> Result<Self, String> {
+        Ok(Self {
+            delta_time: scope.delta_time(),
+            time: scope.time(),
+            phase: scope.phase(),
+        })
+    }
+}
+
+pub trait IntoSystemResult {
+    fn into_system_result(self) -> Result<(), String>;
+}
+
+impl IntoSystemResult for () {
+    fn into_system_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: fmt::Display> IntoSystemResult for Result<(), E> {
+    fn into_system_result(self) -> Result<(), String> {
+        self.map_err(|e| e.to_string())
+    }
+}
+
+// Implemented for functions whose arguments are all `SystemParam`s. `Marker`
+// only exists to keep the per-arity impls apart.
+pub trait SystemParamFunction<Marker>: Send + Sync + 'static {
+    type State: Send + Sync + 'static;
+
+    fn init(access: &mut SystemAccess) -> Self::State;
+    fn call(&mut self, state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<(), String>;
+}
+
+macro_rules! impl_system_param_function {
+    ($($param:ident),*) => {
+        #[allow(non_snake_case, unused_variables, clippy::unused_unit)]
+        impl<Func, Out, $($param: SystemParam),*> SystemParamFunction<fn($($param,)*) -> Out> for Func
+        where
+            Func: FnMut($($param),*) -> Out + Send + Sync + 'static,
+            Out: IntoSystemResult,
+        {
+            type State = ($(<$param as SystemParam>::State,)*);
+
+            fn init(access: &mut SystemAccess) -> Self::State {
+                ($(<$param as SystemParam>::init(access),)*)
+            }
+
+            fn call(&mut self, state: &mut Self::State, world: &mut World, scope: &Rc<ParamScope>) -> Result<(), String> {
+                let ($($param,)*) = state;
+                $(let $param = <$param as SystemParam>::fetch($param, world, scope)?;)*
+                (self)($($param),*).into_system_result()
+            }
+        }
+    };
+}
+
+impl_system_param_function!();
+impl_system_param_function!(P0);
+impl_system_param_function!(P0, P1);
+impl_system_param_function!(P0, P1, P2);
+impl_system_param_function!(P0, P1, P2, P3);
+impl_system_param_function!(P0, P1, P2, P3, P4);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5, P6);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5, P6, P7);
+
+pub struct FunctionSystem<Marker, F: SystemParamFunction<Marker>> {
+    func: F,
+    state: F::State,
+    _marker: PhantomData<fn() -> Marker>,
+}
+
+impl<Marker: 'static, F: SystemParamFunction<Marker>> FunctionSystem<Marker, F> {
+    pub fn new(func: F, access: &mut SystemAccess) -> Self {
+        Self {
+            func,
+            state: F::init(access),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Marker: 'static, F: SystemParamFunction<Marker>> SystemFn for FunctionSystem<Marker, F> {
+    fn run(&mut self, ctx: SystemContext) -> Result<(), String> {
+        let SystemContext { world, delta_time, time, phase } = ctx;
+        let scope = Rc::new(ParamScope {
+            store: RefCell::new(std::mem::replace(&mut world.component_store, ComponentStore::new())),
+            restore: RefCell::new(Vec::new()),
+            deferred: RefCell::new(Vec::new()),
+            delta_time,
+            time,
+            phase,
+        });
+        let guard = ScopeGuard {
+            world,
+            scope: scope.clone(),
+        };
+        self.func.call(&mut self.state, &mut *guard.world, &scope)
+    }
+}
@@ -52,7 +52,10 @@ mod tests {
         
         let changes = delta2.changes;
         println!("{:?}", changes);
-        assert!(changes.iter().any(|c| matches!(c, crate::serialization::DeltaChange::ComponentUpdated { .. })));
+        assert!(changes.iter().any(|c| matches!(
+            c,
+            tx2_link::DeltaChange::ComponentUpdated { .. } | tx2_link::DeltaChange::FieldsUpdated { .. }
+        )));
     }
 
     #[test]
@@ -106,10 +109,2101 @@ mod tests {
 
         // Run 4 times. Default handler disables after 3 failures.
         for _ in 0..4 {
-            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.16, 0.0);
+            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.16, 0.0).unwrap();
         }
 
         // We can't easily inspect the system state from here because it's wrapped in Arc<Mutex>.
         // But we can verify it doesn't panic.
     }
+
+    #[test]
+    fn test_run_conditions() {
+        use crate::system::{System, SystemPhase, SystemScheduler};
+        use crate::condition::{resource_exists, resource_equals, RunCondition};
+        use std::collections::HashSet;
+        use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+
+        #[derive(PartialEq)]
+        struct Paused(bool);
+        struct DebugOverlay;
+
+        let mut world = World::new();
+        let mut scheduler = SystemScheduler::new();
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = runs.clone();
+        let system = System::new(
+            "gated".to_string(),
+            "Gated".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |_ctx: crate::system::SystemContext| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        )
+        .with_run_condition(
+            resource_equals(Paused(false))
+                .and(!resource_exists::<DebugOverlay>().or(RunCondition::new(|_| false))),
+        );
+        scheduler.add(system);
+
+        // No Paused resource yet: skipped.
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        world.insert_resource(Paused(false));
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        world.insert_resource(DebugOverlay);
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let stats = scheduler.stats("gated", SystemPhase::Update).unwrap();
+        assert_eq!(stats.run_count, 1);
+        assert_eq!(stats.skip_count, 2);
+        assert_eq!(scheduler.total_skipped(), 2);
+    }
+
+    #[test]
+    fn test_system_sets() {
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler, SystemSet};
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let make = |id: &str, priority: i32| {
+            let log = log.clone();
+            let name = id.to_string();
+            System::new(
+                id.to_string(),
+                id.to_string(),
+                HashSet::from([SystemPhase::Update]),
+                priority,
+                HashSet::new(),
+                HashSet::new(),
+                Box::new(move |_ctx: SystemContext| {
+                    log.lock().unwrap().push(name.clone());
+                    Ok(())
+                }),
+            )
+        };
+
+        let mut world = World::new();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add_set(SystemSet::new("input").before("physics"));
+        scheduler.add_set(SystemSet::new("physics").with_priority(10));
+        scheduler.add_set(SystemSet::new("collision").in_set("physics"));
+
+        scheduler.add(make("read_input", 0).in_set("input"));
+        scheduler.add(make("apply_velocity", 5).in_set("physics"));
+        scheduler.add(make("resolve", 0).in_set("collision"));
+        let mut render = make("render", 100);
+        render.run_after.insert("physics".to_string());
+        scheduler.add(render);
+
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["read_input", "apply_velocity", "resolve", "render"]
+        );
+
+        log.lock().unwrap().clear();
+        scheduler.set_set_enabled("physics", false);
+        assert!(!scheduler.is_set_enabled("collision"));
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["read_input", "render"]);
+    }
+
+    #[test]
+    fn test_world_frame_loop() {
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+
+        let fixed_runs = Arc::new(AtomicU32::new(0));
+        let counter = fixed_runs.clone();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::new(
+            "physics".to_string(),
+            "Physics".to_string(),
+            HashSet::from([SystemPhase::FixedUpdate]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |ctx: SystemContext| {
+                assert!((ctx.delta_time - 0.1).abs() < 1e-9);
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        ));
+
+        let mut world = World::new();
+        world.set_fixed_timestep(0.1);
+
+        let frame = world.run_frame(&mut scheduler, 0.25).unwrap();
+        assert_eq!(frame.fixed_steps, 2);
+        assert_eq!(fixed_runs.load(Ordering::SeqCst), 2);
+        assert!((frame.alpha - 0.5).abs() < 1e-6);
+        assert!((world.time() - 0.25).abs() < 1e-9);
+
+        world.set_time_scale(2.0);
+        let frame = world.run_frame(&mut scheduler, 0.1).unwrap();
+        assert_eq!(frame.fixed_steps, 2);
+        assert!((frame.delta_time - 0.2).abs() < 1e-9);
+
+        world.pause();
+        let frame = world.run_frame(&mut scheduler, 0.5).unwrap();
+        assert_eq!(frame.fixed_steps, 0);
+        assert_eq!(frame.delta_time, 0.0);
+        world.step_once();
+        let frame = world.run_frame(&mut scheduler, 0.5).unwrap();
+        assert_eq!(frame.fixed_steps, 1);
+        assert_eq!(fixed_runs.load(Ordering::SeqCst), 5);
+
+        // A long stall is clamped and the backlog dropped.
+        world.resume();
+        world.set_time_scale(1.0);
+        world.set_max_fixed_steps(1);
+        let frame = world.run_frame(&mut scheduler, 10.0).unwrap();
+        assert_eq!(frame.fixed_steps, 1);
+        assert!(frame.alpha < 1.0);
+        assert_eq!(world.frame_count(), 5);
+    }
+
+    #[test]
+    fn test_init_and_cleanup_lifecycle() {
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let make = |id: &str, priority: i32| {
+            let log = log.clone();
+            let name = id.to_string();
+            System::new(
+                id.to_string(),
+                id.to_string(),
+                HashSet::from([SystemPhase::Init, SystemPhase::Update, SystemPhase::Cleanup]),
+                priority,
+                HashSet::new(),
+                HashSet::new(),
+                Box::new(move |ctx: SystemContext| {
+                    log.lock().unwrap().push(format!("{}:{:?}", name, ctx.phase));
+                    Ok(())
+                }),
+            )
+        };
+
+        let mut world = World::new();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(make("a", 10));
+        scheduler.add(make("b", 0));
+
+        scheduler.execute_phase(SystemPhase::Init, &mut world, 0.0, 0.0).unwrap();
+        scheduler.execute_phase(SystemPhase::Init, &mut world, 0.0, 0.0).unwrap();
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+
+        // Added mid-run: initialized right before its first update.
+        scheduler.add(make("c", 5));
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert!(scheduler.is_initialized("c"));
+
+        scheduler.shutdown(&mut world).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "a:Init", "b:Init", "a:Update", "b:Update",
+                "c:Init", "a:Update", "c:Update", "b:Update",
+                "b:Cleanup", "c:Cleanup", "a:Cleanup",
+            ]
+        );
+
+        // Dropping an initialized scheduler still runs Cleanup.
+        log.lock().unwrap().clear();
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        drop(scheduler);
+        assert_eq!(log.lock().unwrap().last().map(String::as_str), Some("a:Cleanup"));
+
+        // A system disabled at Init is initialized once it is enabled and runs.
+        log.lock().unwrap().clear();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(make("d", 0));
+        scheduler.disable("d");
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert!(!scheduler.is_initialized("d"));
+        scheduler.enable("d");
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert!(scheduler.is_initialized("d"));
+        scheduler.shutdown(&mut world).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["d:Init", "d:Update", "d:Cleanup"]);
+    }
+
+    #[test]
+    fn test_system_retry_strategy() {
+        use crate::error::{SystemErrorContext, SystemErrorStrategy};
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex, atomic::{AtomicU32, Ordering}};
+
+        static FAILURES: Mutex<Vec<(String, SystemPhase, u32, u64)>> = Mutex::new(Vec::new());
+        fn retry_handler(ctx: &SystemErrorContext) -> SystemErrorStrategy {
+            FAILURES.lock().unwrap().push((ctx.system_id.clone(), ctx.phase, ctx.attempt, ctx.frame));
+            SystemErrorStrategy::Retry
+        }
+
+        let flaky_calls = Arc::new(AtomicU32::new(0));
+        let calls = flaky_calls.clone();
+        let flaky = System::new(
+            "flaky".to_string(),
+            "Flaky".to_string(),
+            HashSet::from([SystemPhase::Update, SystemPhase::FixedUpdate]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |ctx: SystemContext| {
+                if ctx.phase == SystemPhase::FixedUpdate {
+                    return Ok(());
+                }
+                // Fails twice, then succeeds on the second retry.
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    return Err("transient".to_string());
+                }
+                Ok(())
+            }),
+        )
+        .with_error_handler(retry_handler)
+        .with_retry(2, 0);
+
+        let broken_calls = Arc::new(AtomicU32::new(0));
+        let calls = broken_calls.clone();
+        let broken = System::new(
+            "broken".to_string(),
+            "Broken".to_string(),
+            HashSet::from([SystemPhase::LateUpdate]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |_ctx: SystemContext| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err("permanent".to_string())
+            }),
+        )
+        .with_error_handler(retry_handler)
+        .with_retry(1, 2);
+
+        let mut world = World::new();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(flaky);
+        scheduler.add(broken);
+
+        world.run_frame(&mut scheduler, 0.0).unwrap();
+        assert_eq!(flaky_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(scheduler.stats("flaky", SystemPhase::Update).unwrap().failure_count, 0);
+        assert_eq!(broken_calls.load(Ordering::SeqCst), 2);
+
+        // Backed off for two frames, then tried again.
+        world.run_frame(&mut scheduler, 0.0).unwrap();
+        world.run_frame(&mut scheduler, 0.0).unwrap();
+        assert_eq!(broken_calls.load(Ordering::SeqCst), 2);
+        world.run_frame(&mut scheduler, 0.0).unwrap();
+        assert_eq!(broken_calls.load(Ordering::SeqCst), 4);
+        assert_eq!(scheduler.stats("broken", SystemPhase::LateUpdate).unwrap().skip_count, 2);
+
+        let failures = FAILURES.lock().unwrap();
+        assert_eq!(
+            failures[..4],
+            [
+                ("flaky".to_string(), SystemPhase::Update, 0, 1),
+                ("flaky".to_string(), SystemPhase::Update, 1, 1),
+                ("broken".to_string(), SystemPhase::LateUpdate, 0, 1),
+                ("broken".to_string(), SystemPhase::LateUpdate, 1, 1),
+            ]
+        );
+        assert_eq!(failures[4], ("broken".to_string(), SystemPhase::LateUpdate, 0, 4));
+        drop(failures);
+
+        // Backoff counts frames, not runs: a fixed-step system that fails on
+        // the first of four steps sits out the rest of the frame and the next.
+        let stepper_calls = Arc::new(AtomicU32::new(0));
+        let calls = stepper_calls.clone();
+        let stepper = System::new(
+            "stepper".to_string(),
+            "Stepper".to_string(),
+            HashSet::from([SystemPhase::FixedUpdate]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |_ctx: SystemContext| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err("permanent".to_string())
+            }),
+        )
+        .with_error_handler(retry_handler)
+        .with_retry(0, 1);
+        let mut world = World::new();
+        world.set_fixed_timestep(0.01);
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(stepper);
+        world.run_frame(&mut scheduler, 0.04).unwrap();
+        world.run_frame(&mut scheduler, 0.04).unwrap();
+        assert_eq!(stepper_calls.load(Ordering::SeqCst), 1);
+        world.run_frame(&mut scheduler, 0.04).unwrap();
+        assert_eq!(stepper_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_scheduler_ordering_diagnostics() {
+        use crate::error::TX2Error;
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+
+        fn noop(id: &str, priority: i32, before: &[&str], after: &[&str]) -> System {
+            System::new(
+                id.to_string(),
+                id.to_string(),
+                HashSet::from([SystemPhase::Update]),
+                priority,
+                before.iter().map(|s| s.to_string()).collect(),
+                after.iter().map(|s| s.to_string()).collect(),
+                Box::new(|_ctx: SystemContext| Ok(())),
+            )
+        }
+
+        // A high-priority system constrained to run before a low-priority one is still scheduled.
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(noop("first", 10, &["second"], &[]));
+        scheduler.add(noop("second", 0, &[], &[]));
+        scheduler.add(noop("third", 5, &[], &["second"]));
+        scheduler.recompute_execution_order().unwrap();
+        let mut world = World::new();
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        assert_eq!(scheduler.stats("first", SystemPhase::Update).unwrap().run_count, 1);
+        assert_eq!(scheduler.stats("third", SystemPhase::Update).unwrap().run_count, 1);
+
+        // Cycles are reported with their full path instead of panicking.
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(noop("a", 0, &[], &["c"]));
+        scheduler.add(noop("b", 0, &[], &["a"]));
+        scheduler.add(noop("c", 0, &["a"], &["b"]));
+        scheduler.add(noop("d", 0, &[], &[]));
+        match scheduler.recompute_execution_order() {
+            Err(TX2Error::SystemCycle { phase, cycle }) => {
+                assert_eq!(phase, SystemPhase::Update);
+                assert_eq!(cycle, vec!["a", "b", "c", "a"]);
+            }
+            other => panic!("expected a cycle error, got {:?}", other),
+        }
+        assert!(scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).is_err());
+
+        // Data conflicts without ordering are reported as ambiguities.
+        let position = std::any::type_name::<Position>().to_string();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(noop("movement", 0, &[], &[]).with_writes(vec![position.clone()]));
+        scheduler.add(noop("render", 0, &[], &[]).with_reads(vec![position.clone()]));
+        scheduler.add(noop("audio", 0, &[], &[]).with_reads(vec![position.clone()]));
+        scheduler.recompute_execution_order().unwrap();
+        let pairs: Vec<(String, String)> = scheduler.ambiguities().iter()
+            .map(|a| (a.first.clone(), a.second.clone()))
+            .collect();
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs.iter().all(|(a, b)| a == "movement" || b == "movement"));
+
+        scheduler.remove("render");
+        scheduler.add(noop("render", 0, &[], &["movement"]).with_reads(vec![position.clone()]));
+        scheduler.add(noop("late", 0, &[], &["render"]).with_writes(vec![position]));
+        scheduler.recompute_execution_order().unwrap();
+        let pairs: Vec<(String, String)> = scheduler.ambiguities().iter()
+            .map(|a| (a.first.clone(), a.second.clone()))
+            .collect();
+        assert_eq!(pairs, vec![("audio".to_string(), "movement".to_string()), ("audio".to_string(), "late".to_string())]);
+    }
+
+    #[test]
+    fn test_system_profiling() {
+        use crate::profiling::TimingWindow;
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+
+        let mut window = TimingWindow::new(100);
+        for i in 1..=200 {
+            window.record(i as f64);
+        }
+        let summary = window.summary();
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.min_ms, 101.0);
+        assert_eq!(summary.max_ms, 200.0);
+        assert_eq!(summary.avg_ms, 150.5);
+        assert_eq!(summary.p99_ms, 199.0);
+
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::new(
+            "slow".to_string(),
+            "Slow".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(|_ctx: SystemContext| {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                Ok(())
+            }),
+        ));
+        scheduler.add(System::new(
+            "failing".to_string(),
+            "Failing".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(|_ctx: SystemContext| Err("nope".to_string())),
+        ).with_error_handler(|_| crate::error::SystemErrorStrategy::Ignore));
+
+        let mut world = World::new();
+        for _ in 0..3 {
+            world.run_frame(&mut scheduler, 0.016).unwrap();
+        }
+
+        let slow = scheduler.stats("slow", SystemPhase::Update).unwrap();
+        assert_eq!(slow.run_count, 3);
+        assert!(slow.summary().min_ms >= 2.0);
+        assert_eq!(scheduler.stats("failing", SystemPhase::Update).unwrap().failure_count, 3);
+        assert_eq!(scheduler.phase_stats(SystemPhase::Update).unwrap().run_count, 3);
+
+        let report = scheduler.profile_report();
+        assert_eq!(report.over_budget(1.0).len(), 1);
+        assert_eq!(report.over_budget(1.0)[0].system_id, "slow");
+
+        let json: serde_json::Value = serde_json::from_str(&scheduler.export_profile_json()).unwrap();
+        assert_eq!(json["systems"][1]["system_id"], "slow");
+        assert_eq!(json["systems"][1]["calls"], 3);
+        assert!(json["systems"][1]["p99_ms"].as_f64().unwrap() >= 2.0);
+        assert_eq!(json["phases"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_runtime_system_management() {
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+
+        struct ConsoleInput(Vec<&'static str>);
+
+        let counter = Arc::new(AtomicU32::new(0));
+        let calls = counter.clone();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::new(
+            "console".to_string(),
+            "Debug Console".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            100,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(|ctx: SystemContext| {
+                let commands = ctx.world.remove_resource::<ConsoleInput>().map(|c| c.0).unwrap_or_default();
+                for command in commands {
+                    match command {
+                        "disable counter" => ctx.world.system_commands().disable("counter"),
+                        "enable counter" => ctx.world.system_commands().enable("counter"),
+                        "retry failed" => ctx.world.system_commands().reenable_failed(),
+                        _ => {}
+                    }
+                }
+                Ok(())
+            }),
+        ));
+        scheduler.add(System::new(
+            "counter".to_string(),
+            "Counter".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |_ctx: SystemContext| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        ));
+        scheduler.add(System::new(
+            "fragile".to_string(),
+            "Fragile".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(|_ctx: SystemContext| Err("broken".to_string())),
+        ));
+
+        let mut world = World::new();
+        for _ in 0..3 {
+            world.run_frame(&mut scheduler, 0.016).unwrap();
+        }
+        let fragile = scheduler.get_system_info("fragile").unwrap();
+        assert!(!fragile.enabled);
+        assert!(fragile.disabled_by_error);
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+
+        // Disabled from inside the frame, before "counter" gets its turn.
+        world.insert_resource(ConsoleInput(vec!["disable counter", "retry failed"]));
+        world.run_frame(&mut scheduler, 0.016).unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+        assert!(!scheduler.is_enabled("counter"));
+        let fragile = scheduler.get_system_info("fragile").unwrap();
+        assert!(fragile.enabled && fragile.consecutive_failures == 1);
+
+        world.system_commands().enable("counter");
+        world.system_commands().set_priority("counter", 200);
+        world.run_frame(&mut scheduler, 0.016).unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+
+        let systems = scheduler.list_systems();
+        let ids: Vec<&str> = systems.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["console", "counter", "fragile"]);
+        assert_eq!(systems[1].priority, 200);
+        assert_eq!(systems[0].phases, vec!["update"]);
+        assert!(!scheduler.set_priority("missing", 1));
+    }
+
+    #[test]
+    fn test_panic_isolation() {
+        use crate::error::{SystemErrorContext, SystemErrorStrategy};
+        use crate::system::{System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::{Arc, Mutex, atomic::{AtomicU32, Ordering}};
+
+        static REPORTS: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+        fn record(ctx: &SystemErrorContext) -> SystemErrorStrategy {
+            REPORTS.lock().unwrap().push((ctx.error.clone(), ctx.panicked));
+            SystemErrorStrategy::Ignore
+        }
+
+        let survivor_runs = Arc::new(AtomicU32::new(0));
+        let runs = survivor_runs.clone();
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::new(
+            "buggy_mod".to_string(),
+            "Buggy Mod".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            10,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(|_ctx: SystemContext| panic!("index out of bounds")),
+        ).with_error_handler(record));
+        scheduler.add(System::new(
+            "survivor".to_string(),
+            "Survivor".to_string(),
+            HashSet::from([SystemPhase::Update]),
+            0,
+            HashSet::new(),
+            HashSet::new(),
+            Box::new(move |_ctx: SystemContext| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        ));
+
+        let mut world = World::new();
+
+        // Without isolation the panic unwinds out of the frame and poisons the lock.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0)
+        }));
+        assert!(result.is_err());
+        assert_eq!(survivor_runs.load(Ordering::SeqCst), 0);
+
+        scheduler.set_panic_isolation(true);
+        for _ in 0..2 {
+            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        }
+        assert_eq!(survivor_runs.load(Ordering::SeqCst), 2);
+        assert_eq!(scheduler.stats("buggy_mod", SystemPhase::Update).unwrap().failure_count, 2);
+        assert_eq!(
+            *REPORTS.lock().unwrap(),
+            vec![
+                ("panicked: index out of bounds".to_string(), true),
+                ("panicked: index out of bounds".to_string(), true),
+            ]
+        );
+        assert!(scheduler.get_system_info("buggy_mod").unwrap().enabled);
+    }
+
+    #[test]
+    fn test_custom_phases_and_schedules() {
+        use crate::error::TX2Error;
+        use crate::system::{PhaseAnchor, System, SystemContext, SystemPhase, SystemScheduler};
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex};
+
+        const PRE_PHYSICS: SystemPhase = SystemPhase::Custom("prePhysics");
+        let network = SystemPhase::custom("network");
+        let render = SystemPhase::custom("render");
+        let replay = SystemPhase::custom("replay");
+        assert_eq!(network, SystemPhase::custom(&String::from("network")));
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut scheduler = SystemScheduler::new();
+        for (id, phase) in [
+            ("physics", SystemPhase::FixedUpdate),
+            ("pre_physics", PRE_PHYSICS),
+            ("logic", SystemPhase::Update),
+            ("network", network),
+            ("render", render),
+            ("replay", replay),
+        ] {
+            let log = log.clone();
+            scheduler.add(System::new(
+                id.to_string(),
+                id.to_string(),
+                HashSet::from([phase]),
+                0,
+                HashSet::new(),
+                HashSet::new(),
+                Box::new(move |_ctx: SystemContext| {
+                    log.lock().unwrap().push(id);
+                    Ok(())
+                }),
+            ));
+        }
+
+        let mut world = World::new();
+        assert!(matches!(
+            scheduler.execute_phase(network, &mut world, 0.0, 0.0),
+            Err(TX2Error::UnknownPhase { .. })
+        ));
+
+        scheduler.register_phase(PRE_PHYSICS, PhaseAnchor::Before(SystemPhase::FixedUpdate));
+        scheduler.register_phase(network, PhaseAnchor::After(SystemPhase::Update));
+        scheduler.register_phase(render, PhaseAnchor::After(network));
+        scheduler.register_schedule(replay);
+
+        world.set_fixed_timestep(0.1);
+        world.run_frame(&mut scheduler, 0.1).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["pre_physics", "physics", "logic", "network", "render"]
+        );
+
+        log.lock().unwrap().clear();
+        scheduler.run_schedule(replay, &mut world, 0.0, 0.0).unwrap();
+        scheduler.execute_phase(render, &mut world, 0.0, 0.0).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["replay", "render"]);
+        assert_eq!(scheduler.get_system_info("network").unwrap().phases, vec!["network"]);
+
+        let duplicate = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            scheduler.register_schedule(replay);
+        }));
+        assert!(duplicate.is_err());
+        let bad_anchor = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            scheduler.register_phase(SystemPhase::custom("late"), PhaseAnchor::After(replay));
+        }));
+        assert!(bad_anchor.is_err());
+    }
+
+    #[test]
+    fn test_function_systems() {
+        use crate::entity::EntityId;
+        use crate::system::{System, SystemPhase, SystemScheduler};
+        use crate::system_param::{Commands, EventReader, EventWriter, Local, Query, Res, ResMut, Time, Without};
+        use std::collections::HashSet;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Velocity {
+            x: f64,
+            y: f64,
+        }
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Frozen;
+
+        struct Gravity(f64);
+        #[derive(Default)]
+        struct Score(u32);
+        #[derive(Clone, Debug, PartialEq)]
+        struct Collected(EntityId);
+
+        fn apply_gravity(gravity: Res<Gravity>, time: Time, mut query: Query<&mut Velocity, Without<Frozen>>) {
+            query.for_each(|_, velocity| velocity.y -= gravity.0 * time.delta_time);
+        }
+
+        fn movement(mut query: Query<(&mut Position, &Velocity)>, time: Time) {
+            query.for_each(|_, (position, velocity)| {
+                position.x += velocity.x * time.delta_time;
+                position.y += velocity.y * time.delta_time;
+            });
+        }
+
+        fn collect(mut commands: Commands, mut events: EventWriter<Collected>, mut positions: Query<&Position>) {
+            for entity in positions.entities() {
+                let fallen = positions.get(entity, |p| p.y < 0.0).unwrap_or(false);
+                if fallen {
+                    commands.despawn(entity);
+                    events.send(Collected(entity));
+                }
+            }
+        }
+
+        fn scoring(events: EventReader<Collected>, mut score: ResMut<Score>, mut seen: Local<u32>) {
+            *seen += events.len() as u32;
+            score.0 = *seen;
+        }
+
+        let update = || HashSet::from([SystemPhase::Update]);
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::from_fn("gravity", update(), apply_gravity).with_priority(30));
+        scheduler.add(System::from_fn("movement", update(), movement).after("gravity"));
+        scheduler.add(System::from_fn("collect", update(), collect).after("movement"));
+        scheduler.add(System::from_fn("scoring", update(), scoring).after("collect"));
+
+        // Access sets come from the parameter types.
+        let movement_info = scheduler.get_system_info("movement").unwrap();
+        assert_eq!(movement_info.writes, vec![std::any::type_name::<Position>().to_string()]);
+        assert_eq!(movement_info.reads, vec![std::any::type_name::<Velocity>().to_string()]);
+
+        let mut world = World::new();
+        world.insert_resource(Gravity(10.0));
+        world.insert_resource(Score::default());
+        world.add_event::<Collected>();
+        let falling = world.create_entity().id;
+        world.add_component(falling, Box::new(Position { x: 0.0, y: 0.5 }));
+        world.add_component(falling, Box::new(Velocity { x: 1.0, y: 0.0 }));
+        let frozen = world.create_entity().id;
+        world.add_component(frozen, Box::new(Position { x: 5.0, y: 0.5 }));
+        world.add_component(frozen, Box::new(Velocity { x: 0.0, y: 0.0 }));
+        world.add_component(frozen, Box::new(Frozen));
+
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.1, 0.1).unwrap();
+        assert_eq!(world.get_component::<Velocity>(falling).unwrap().y, -1.0);
+        let position = world.get_component::<Position>(falling).unwrap();
+        assert!((position.x - 0.1).abs() < 1e-9 && (position.y - 0.4).abs() < 1e-9);
+        assert!(world.has_resource::<Gravity>());
+
+        for _ in 0..3 {
+            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.1, 0.1).unwrap();
+            world.update_events();
+        }
+        assert!(!world.has_entity(falling));
+        assert!(world.has_entity(frozen));
+        assert_eq!(world.get_resource::<Score>().unwrap().0, 1);
+
+        // A missing resource fails the system instead of panicking.
+        world.remove_resource::<Gravity>();
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.1, 0.1).unwrap();
+        assert_eq!(scheduler.stats("gravity", SystemPhase::Update).unwrap().failure_count, 1);
+
+        fn conflicting(_a: Query<&mut Position>, _b: Query<&Position>) {}
+        let rejected = std::panic::catch_unwind(|| System::from_fn("conflicting", update(), conflicting));
+        assert!(rejected.is_err());
+
+        // Event writers are deferred, so one system may read and write an event type.
+        #[derive(Clone, Debug, PartialEq)]
+        struct Ping(u32);
+        fn echo(pings: EventReader<Ping>, mut replies: EventWriter<Ping>, mut last: ResMut<Score>) {
+            for ping in pings.iter() {
+                last.0 = ping.0;
+                replies.send(Ping(ping.0 + 1));
+            }
+        }
+        let echo = System::from_fn("echo", update(), echo);
+        assert!(echo.writes.contains(&crate::system_param::resource_access_id::<crate::event::Events<Ping>>()));
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(echo);
+        world.add_event::<Ping>();
+        world.send_event(Ping(0));
+        for _ in 0..3 {
+            scheduler.execute_phase(SystemPhase::Update, &mut world, 0.1, 0.1).unwrap();
+        }
+        assert_eq!(world.get_resource::<Score>().unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_state_machine() {
+        use crate::condition::in_state;
+        use crate::state::{NextState, State, on_enter, on_exit};
+        use crate::system::{System, SystemPhase, SystemScheduler};
+        use crate::system_param::ResMut;
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        enum AppState {
+            Menu,
+            Loading,
+            Playing,
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut scheduler = SystemScheduler::new();
+        let mut world = World::new();
+        scheduler.add_state(&mut world, AppState::Menu);
+
+        let logger = |label: &'static str, phase: SystemPhase| {
+            let log = log.clone();
+            System::from_fn(label, HashSet::from([phase]), move || log.lock().unwrap().push(label))
+        };
+        scheduler.add(logger("enter_menu", on_enter(&AppState::Menu)));
+        scheduler.add(logger("exit_menu", on_exit(&AppState::Menu)));
+        scheduler.add(logger("enter_playing", on_enter(&AppState::Playing)));
+        scheduler.add(logger("gameplay", SystemPhase::Update).with_run_condition(in_state(AppState::Playing)));
+        // Loading finishes immediately; the follow-up transition lands next frame.
+        scheduler.add(System::from_fn(
+            "finish_loading",
+            HashSet::from([on_enter(&AppState::Loading)]),
+            |mut next: ResMut<NextState<AppState>>| next.set(AppState::Playing),
+        ));
+
+        world.run_frame(&mut scheduler, 0.016).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["enter_menu"]);
+
+        world.get_resource_mut::<NextState<AppState>>().unwrap().set(AppState::Loading);
+        world.run_frame(&mut scheduler, 0.016).unwrap();
+        assert_eq!(*world.get_resource::<State<AppState>>().unwrap().get(), AppState::Loading);
+        assert_eq!(*log.lock().unwrap(), vec!["enter_menu", "exit_menu"]);
+
+        world.run_frame(&mut scheduler, 0.016).unwrap();
+        let state = world.get_resource::<State<AppState>>().unwrap();
+        assert_eq!((*state.get(), state.previous().copied()), (AppState::Playing, Some(AppState::Loading)));
+        assert_eq!(*log.lock().unwrap(), vec!["enter_menu", "exit_menu", "enter_playing", "gameplay"]);
+    }
+
+    #[test]
+    fn test_snapshot_restore_with_registry() {
+        use crate::component::{DynamicComponent, UnknownComponentPolicy};
+        use crate::error::TX2Error;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Health(u32);
+
+        let mut source = World::new();
+        let entity = source.create_entity().id;
+        source.add_component(entity, Box::new(Position { x: 1.0, y: 2.0 }));
+        source.add_component(entity, Box::new(Health(75)));
+        let snapshot = source.create_snapshot();
+
+        // Health is not registered on the receiving side.
+        let mut world = World::new();
+        world.register_component::<Position>();
+        let unknown = world.restore_from_snapshot(&snapshot);
+        assert!(matches!(unknown, Err(TX2Error::UnknownComponent { .. })));
+        assert!(world.get_all_entities().is_empty());
+
+        let report = world.restore_from_snapshot_with(&snapshot, UnknownComponentPolicy::Skip).unwrap();
+        assert_eq!((report.entities, report.components), (1, 1));
+        assert_eq!(report.skipped, vec![(entity, std::any::type_name::<Health>().to_string())]);
+        assert_eq!(world.get_component::<Position>(entity), Some(&Position { x: 1.0, y: 2.0 }));
+
+        world.set_unknown_component_policy(UnknownComponentPolicy::KeepDynamic);
+        let report = world.restore_from_snapshot(&snapshot).unwrap();
+        assert_eq!(report.dynamic.len(), 1);
+        let health = world.get_all_components(entity).into_iter()
+            .find_map(|c| c.as_any().downcast_ref::<DynamicComponent>())
+            .unwrap();
+        assert_eq!(health.id, std::any::type_name::<Health>());
+        assert_eq!(health.data, serde_json::json!(75));
+
+        world.register_component::<Health>();
+        world.restore_from_snapshot(&snapshot).unwrap();
+        assert_eq!(world.get_component::<Health>(entity), Some(&Health(75)));
+
+        // Restored ids are never handed out again.
+        assert!(world.create_entity().id > entity);
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        use crate::serialization::DeltaMismatch;
+        use tx2_link::{Delta, DeltaChange};
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Name(String);
+
+        let mut server = World::new();
+        let mut client = World::new();
+        client.register_component::<Position>();
+        client.register_component::<Name>();
+        let mut compressor = DeltaCompressor::new();
+
+        let player = server.create_entity().id;
+        server.add_component(player, Box::new(Position { x: 0.0, y: 0.0 }));
+        server.add_component(player, Box::new(Name("p1".to_string())));
+        let report = client.apply_delta(&compressor.create_delta(&server));
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert_eq!(report.applied, 3);
+
+        // Field-level update.
+        server.remove_component(player, std::any::type_name::<Position>());
+        server.add_component(player, Box::new(Position { x: 4.5, y: 0.0 }));
+        let report = client.apply_delta(&compressor.create_delta(&server));
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert_eq!(client.get_component::<Position>(player), Some(&Position { x: 4.5, y: 0.0 }));
+
+        server.remove_component(player, std::any::type_name::<Name>());
+        let enemy = server.create_entity().id;
+        server.add_component(enemy, Box::new(Position { x: 9.0, y: 9.0 }));
+        let report = client.apply_delta(&compressor.create_delta(&server));
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert!(!client.has_component(player, std::any::type_name::<Name>()));
+        assert_eq!(client.get_component::<Position>(enemy), Some(&Position { x: 9.0, y: 9.0 }));
+
+        server.destroy_entity(enemy);
+        client.apply_delta(&compressor.create_delta(&server));
+        assert!(!client.has_entity(enemy));
+
+        // Replaying stale or malformed changes reports instead of panicking.
+        let bad = Delta {
+            changes: vec![
+                DeltaChange::EntityAdded { entity_id: player },
+                DeltaChange::EntityRemoved { entity_id: enemy },
+                DeltaChange::ComponentRemoved { entity_id: player, component_id: "Missing".to_string() },
+                DeltaChange::ComponentUpdated {
+                    entity_id: player,
+                    component_id: std::any::type_name::<Position>().to_string(),
+                    data: tx2_link::protocol::ComponentData::from_json_value(serde_json::json!({ "x": "nope" })),
+                },
+                DeltaChange::ComponentAdded {
+                    entity_id: player,
+                    component_id: "Unregistered".to_string(),
+                    data: tx2_link::protocol::ComponentData::from_json_value(serde_json::json!(1)),
+                },
+            ],
+            timestamp: 0.0,
+            base_timestamp: 0.0,
+        };
+        let report = client.apply_delta(&bad);
+        assert_eq!(report.applied, 0);
+        assert_eq!(report.mismatches.len(), 5);
+        assert_eq!(report.mismatches[0], DeltaMismatch::EntityExists(player));
+        assert_eq!(report.mismatches[1], DeltaMismatch::EntityMissing(enemy));
+        assert!(matches!(report.mismatches[3], DeltaMismatch::InvalidData { .. }));
+        assert!(matches!(report.mismatches[4], DeltaMismatch::UnknownComponent { .. }));
+        assert_eq!(client.get_component::<Position>(player), Some(&Position { x: 4.5, y: 0.0 }));
+    }
+
+    #[test]
+    fn test_snapshot_versioning() {
+        use crate::error::TX2Error;
+        use crate::serialization::{SnapshotInfo, SNAPSHOT_FORMAT};
+        use crate::system::SystemScheduler;
+        use std::collections::BTreeMap;
+
+        let mut server = World::new();
+        server.register_component::<Position>();
+        let mut scheduler = SystemScheduler::new();
+        for _ in 0..3 {
+            server.run_frame(&mut scheduler, 0.5).unwrap();
+        }
+        let entity = server.create_entity().id;
+        server.add_component(entity, Box::new(Position { x: 1.0, y: 1.0 }));
+
+        let metadata = BTreeMap::from([("map".to_string(), "harbor".to_string())]);
+        let snapshot = server.create_snapshot_with_metadata(metadata.clone());
+        let info = SnapshotInfo::parse(&snapshot).unwrap();
+        assert_eq!(snapshot.timestamp, 0.75);
+        assert_eq!((info.tick, info.time), (3, 0.75));
+        assert_eq!(info.format, SNAPSHOT_FORMAT);
+        assert_eq!(info.schema_version, server.component_registry().schema_version());
+        assert_eq!(info.metadata, metadata);
+
+        let mut client = World::new();
+        client.register_component::<Position>();
+        let report = client.restore_from_snapshot(&snapshot).unwrap();
+        assert!(report.warnings.is_empty());
+        assert_eq!((client.frame_count(), client.time()), (3, 0.75));
+
+        // Registering an extra component changes the schema version.
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Extra;
+        client.register_component::<Extra>();
+        let report = client.restore_from_snapshot(&snapshot).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+
+        let mut legacy = snapshot.clone();
+        legacy.version = "1.0.0".to_string();
+        assert_eq!(client.restore_from_snapshot(&legacy).unwrap().warnings.len(), 1);
+
+        let mut future = snapshot.clone();
+        future.version = future.version.replace(SNAPSHOT_FORMAT, "tx2-core/2");
+        assert!(matches!(
+            client.restore_from_snapshot(&future),
+            Err(TX2Error::IncompatibleSnapshot { .. })
+        ));
+    }
+
+    #[test]
+    fn test_component_migrations() {
+        use crate::component::DynamicComponent;
+        use crate::serialization::{DeltaMismatch, SnapshotInfo};
+        use serde_json::{Value, json};
+        use tx2_link::{Delta, DeltaChange, protocol::ComponentData};
+
+        // v1 was `{ "hp": n }`, v2 renamed it to `health`, v3 added `max`.
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Health {
+            health: u32,
+            max: u32,
+        }
+
+        fn rename_hp(mut value: Value) -> Result<Value, String> {
+            let hp = value.as_object_mut().and_then(|o| o.remove("hp")).ok_or("missing hp")?;
+            Ok(json!({ "health": hp }))
+        }
+
+        fn add_max(mut value: Value) -> Result<Value, String> {
+            let health = value["health"].clone();
+            value["max"] = health;
+            Ok(value)
+        }
+
+        // A save written by the v1 build.
+        let mut old_build = World::new();
+        old_build.register_component::<Health>();
+        let id = old_build.create_entity().id;
+        old_build.add_component(id, Box::new(DynamicComponent {
+            id: std::any::type_name::<Health>().to_string(),
+            data: json!({ "hp": 40 }),
+        }));
+        let save = old_build.create_snapshot();
+
+        let mut world = World::new();
+        world.register_component::<Health>()
+            .with_version(3)
+            .with_migration(1, rename_hp)
+            .with_migration(2, add_max);
+        world.restore_from_snapshot(&save).unwrap();
+        assert_eq!(world.get_component::<Health>(id), Some(&Health { health: 40, max: 40 }));
+
+        // Deltas from the same v1 source are migrated too; field patches are refused.
+        let component_id = std::any::type_name::<Health>().to_string();
+        let delta = Delta {
+            changes: vec![
+                DeltaChange::ComponentUpdated {
+                    entity_id: id,
+                    component_id: component_id.clone(),
+                    data: ComponentData::from_json_value(json!({ "hp": 25 })),
+                },
+                DeltaChange::FieldsUpdated { entity_id: id, component_id: component_id.clone(), fields: vec![] },
+            ],
+            timestamp: 0.0,
+            base_timestamp: 0.0,
+        };
+        let report = world.apply_delta(&delta);
+        assert_eq!(report.applied, 1);
+        assert!(matches!(report.mismatches[0], DeltaMismatch::InvalidData { .. }));
+        assert_eq!(world.get_component::<Health>(id), Some(&Health { health: 25, max: 25 }));
+
+        // Data from a newer build cannot be downgraded.
+        let mut newer = world.create_snapshot();
+        let mut info = SnapshotInfo::parse(&newer).unwrap();
+        info.component_versions.insert(component_id, 4);
+        newer.version = serde_json::to_string(&info).unwrap();
+        assert!(world.restore_from_snapshot(&newer).is_err());
+    }
+
+    #[test]
+    fn test_binary_snapshot_encodings() {
+        use crate::encoding::{BinaryFormat, format_name};
+        use crate::profiling::now_ms;
+        use crate::serialization::SnapshotOptions;
+        use tx2_link::protocol::ComponentData;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Stats {
+            name: String,
+            level: u32,
+            scores: Vec<f32>,
+        }
+
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Stats>();
+        for i in 0..500 {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Position { x: i as f64 * 0.5, y: -(i as f64) }));
+            world.add_component(entity, Box::new(Stats {
+                name: format!("unit-{}", i),
+                level: i % 60,
+                scores: vec![i as f32, 1.5, 2.25],
+            }));
+        }
+
+        let payload_size = |snapshot: &tx2_link::WorldSnapshot| -> usize {
+            snapshot.entities.iter()
+                .flat_map(|e| &e.components)
+                .map(|c| match &c.data {
+                    ComponentData::Json(json) => json.len(),
+                    ComponentData::Binary(bytes) => bytes.len(),
+                    ComponentData::Structured(_) => 0,
+                })
+                .sum()
+        };
+
+        let mut sizes = Vec::new();
+        for format in [BinaryFormat::Json, BinaryFormat::MessagePack, BinaryFormat::Bincode] {
+            let started = now_ms();
+            let snapshot = world.create_snapshot_with(&SnapshotOptions::new().with_format(format));
+            let encode_ms = now_ms() - started;
+
+            let mut restored = World::new();
+            restored.register_component::<Position>();
+            restored.register_component::<Stats>();
+            let started = now_ms();
+            let report = restored.restore_from_snapshot(&snapshot).unwrap();
+            let decode_ms = now_ms() - started;
+            assert_eq!(report.components, 1000);
+
+            for entity in world.get_all_entities() {
+                assert_eq!(restored.get_component::<Position>(entity.id), world.get_component::<Position>(entity.id));
+                assert_eq!(restored.get_component::<Stats>(entity.id), world.get_component::<Stats>(entity.id));
+            }
+
+            let size = payload_size(&snapshot);
+            println!("{:>12}: {:>7} bytes, encode {:.2}ms, decode {:.2}ms", format_name(format), size, encode_ms, decode_ms);
+            sizes.push(size);
+        }
+        assert!(sizes[1] < sizes[0], "MessagePack should be smaller than JSON");
+        assert!(sizes[2] < sizes[1], "bincode should be smaller than MessagePack");
+
+        // Deltas carry whole binary components and decode with the restored format.
+        let mut compressor = crate::serialization::DeltaCompressor::with_format(BinaryFormat::MessagePack);
+        let mut client = World::new();
+        client.register_component::<Position>();
+        client.register_component::<Stats>();
+        client.restore_from_snapshot(&world.create_snapshot_with(&SnapshotOptions::new().with_format(BinaryFormat::MessagePack))).unwrap();
+        compressor.create_delta(&world);
+        let entity = world.create_entity().id;
+        world.add_component(entity, Box::new(Position { x: 3.0, y: 4.0 }));
+        let report = client.apply_delta(&compressor.create_delta(&world));
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert_eq!(client.get_component::<Position>(entity), Some(&Position { x: 3.0, y: 4.0 }));
+    }
+
+    #[test]
+    fn test_save_and_load_world_file() {
+        use crate::encoding::BinaryFormat;
+        use crate::error::TX2Error;
+        use crate::persistence::{self, SaveOptions, HEADER_LEN};
+
+        let dir = std::env::temp_dir().join(format!("tx2-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut world = World::new();
+        world.register_component::<Position>();
+        let mut ids = Vec::new();
+        for i in 0..200 {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Position { x: i as f64, y: 1.0 }));
+            ids.push(entity);
+        }
+
+        for (format, compress) in [
+            (BinaryFormat::Json, false),
+            (BinaryFormat::Json, true),
+            (BinaryFormat::MessagePack, true),
+            (BinaryFormat::Bincode, true),
+        ] {
+            let path = dir.join("world.tx2");
+            world.save_to_with(&path, &SaveOptions::new().with_format(format).with_compression(compress)).unwrap();
+            let leftovers: Vec<_> = std::fs::read_dir(&dir).unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .filter(|name| name != "world.tx2")
+                .collect();
+            assert!(leftovers.is_empty(), "temp files left behind: {:?}", leftovers);
+
+            let mut loaded = World::new();
+            loaded.register_component::<Position>();
+            let report = loaded.load_from(&path).unwrap();
+            assert_eq!(report.entities, 200);
+            for id in &ids {
+                assert_eq!(loaded.get_component::<Position>(*id), world.get_component::<Position>(*id));
+            }
+        }
+
+        // Compression pays off on repetitive snapshot data.
+        let snapshot = world.create_snapshot();
+        let plain = persistence::encode_file(&snapshot, BinaryFormat::Json, false).unwrap();
+        let packed = persistence::encode_file(&snapshot, BinaryFormat::Json, true).unwrap();
+        assert!(packed.len() < plain.len() / 2);
+
+        let is_corrupt = |bytes: &[u8]| matches!(persistence::decode_file(bytes), Err(TX2Error::CorruptFile { .. }));
+        assert!(is_corrupt(&packed[..packed.len() - 10]));
+        assert!(is_corrupt(&packed[..HEADER_LEN - 1]));
+        let mut flipped = packed.clone();
+        flipped[HEADER_LEN + 20] ^= 0x40;
+        assert!(is_corrupt(&flipped));
+        let mut bad_magic = packed.clone();
+        bad_magic[0] = b'X';
+        assert!(is_corrupt(&bad_magic));
+        // Header lengths are checksummed and bounded, so a forged one cannot
+        // force a huge allocation.
+        let mut forged = packed.clone();
+        forged[16..24].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        assert!(is_corrupt(&forged));
+        let mut resealed = forged.clone();
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&resealed[..24]);
+        hasher.update(&resealed[HEADER_LEN..]);
+        resealed[24..28].copy_from_slice(&hasher.finalize().to_le_bytes());
+        assert!(is_corrupt(&resealed));
+
+        // A failed load leaves the world untouched.
+        let path = dir.join("broken.tx2");
+        std::fs::write(&path, &flipped).unwrap();
+        let mut target = World::new();
+        target.register_component::<Position>();
+        assert!(target.load_from(&path).is_err());
+        assert!(target.get_all_entities().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filtered_snapshots() {
+        use crate::query::QueryDescriptor;
+        use crate::serialization::{SnapshotFilter, SnapshotInfo};
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Inventory {
+            items: Vec<String>,
+        }
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Player;
+
+        let position_id = std::any::type_name::<Position>().to_string();
+        let inventory_id = std::any::type_name::<Inventory>().to_string();
+        let player_id = std::any::type_name::<Player>().to_string();
+
+        let mut world = World::new();
+        let mut players = Vec::new();
+        for i in 0..3 {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Position { x: i as f64, y: 0.0 }));
+            world.add_component(entity, Box::new(Inventory { items: vec![format!("sword-{}", i)] }));
+            world.add_component(entity, Box::new(Player));
+            players.push(entity);
+        }
+        let rock = world.create_entity().id;
+        world.add_component(rock, Box::new(Position { x: 9.0, y: 9.0 }));
+
+        let full = world.create_snapshot();
+        assert_eq!(full.entities.len(), 4);
+        assert!(!SnapshotInfo::parse(&full).unwrap().partial);
+
+        // Query: only players.
+        let snapshot = world.create_snapshot_filtered(&SnapshotFilter::new().with_query(QueryDescriptor {
+            all: vec![player_id.clone()],
+            ..Default::default()
+        }));
+        assert_eq!(snapshot.entities.iter().map(|e| e.id).collect::<Vec<_>>(), players);
+        assert!(SnapshotInfo::parse(&snapshot).unwrap().partial);
+
+        // Include list: replicated components only; the rock has none of them.
+        let snapshot = world.create_snapshot_filtered(&SnapshotFilter::new().include([inventory_id.as_str()]));
+        assert_eq!(snapshot.entities.len(), 3);
+        assert!(snapshot.entities.iter().all(|e| e.components.len() == 1 && e.components[0].id == inventory_id));
+
+        // Entity set combined with an include list: one player's inventory.
+        let snapshot = world.create_snapshot_filtered(
+            &SnapshotFilter::new().with_entities([players[1]]).include([inventory_id.as_str()]),
+        );
+        assert_eq!(snapshot.entities.len(), 1);
+        assert_eq!(snapshot.entities[0].id, players[1]);
+        assert_eq!(snapshot.entities[0].components.len(), 1);
+
+        // Restoring it merges: that inventory is rolled back and nothing else moves.
+        world.register_component::<Position>();
+        world.register_component::<Inventory>();
+        world.register_component::<Player>();
+        world.get_component_mut::<Inventory>(players[1]).unwrap().items.clear();
+        world.get_component_mut::<Position>(players[1]).unwrap().x = 5.0;
+        let report = world.restore_from_snapshot(&snapshot).unwrap();
+        assert_eq!((report.entities, report.components), (1, 1));
+        assert_eq!(world.get_all_entities().len(), 4);
+        assert_eq!(world.get_component::<Inventory>(players[1]).unwrap().items, ["sword-1"]);
+        assert_eq!(world.get_component::<Position>(players[1]).unwrap().x, 5.0);
+        let mut empty = World::new();
+        empty.register_component::<Inventory>();
+        empty.restore_from_snapshot(&snapshot).unwrap();
+        assert_eq!(empty.get_all_components(players[1]).len(), 1);
+
+        // Exclude list keeps entities that still have something left.
+        let snapshot = world.create_snapshot_filtered(&SnapshotFilter::new().exclude([position_id.as_str()]));
+        assert_eq!(snapshot.entities.len(), 3);
+        assert!(snapshot.entities.iter().flat_map(|e| &e.components).all(|c| c.id != position_id));
+
+        // Filtered deltas only report changes inside the filter.
+        let options = crate::serialization::SnapshotOptions::new()
+            .with_filter(SnapshotFilter::new().include([position_id.as_str()]));
+        let mut compressor = DeltaCompressor::with_options(options);
+        compressor.create_delta(&world);
+        world.add_component(players[0], Box::new(Inventory { items: vec![] }));
+        assert!(compressor.create_delta(&world).changes.is_empty());
+        // Adding a component the entity already has replaces it.
+        world.add_component(rock, Box::new(Position { x: 1.0, y: 1.0 }));
+        assert_eq!(world.get_component::<Position>(rock), Some(&Position { x: 1.0, y: 1.0 }));
+        assert_eq!(world.get_all_components(rock).len(), 1);
+        assert_eq!(compressor.create_delta(&world).changes.len(), 1);
+    }
+
+    #[test]
+    fn test_transient_components() {
+        use crate::persistence::{decode_file, encode_file};
+        use crate::encoding::BinaryFormat;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Mesh {
+            path: String,
+        }
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+        struct GpuHandle {
+            buffer: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Scratch(Vec<f32>);
+
+        let handle_id = std::any::type_name::<GpuHandle>().to_string();
+        let scratch_id = std::any::type_name::<Scratch>().to_string();
+
+        let mut world = World::new();
+        world.register_component::<Mesh>();
+        world.register_component::<Position>();
+        world.rebuild_transient::<GpuHandle, Mesh>();
+        world.mark_transient::<Scratch>();
+
+        let ship = world.create_entity().id;
+        world.add_component(ship, Box::new(Mesh { path: "ship.glb".into() }));
+        world.add_component(ship, Box::new(GpuHandle { buffer: 42 }));
+        world.add_component(ship, Box::new(Scratch(vec![1.0; 16])));
+        let marker = world.create_entity().id;
+        world.add_component(marker, Box::new(Position { x: 1.0, y: 2.0 }));
+
+        let snapshot = world.create_snapshot();
+        let ids: Vec<&String> = snapshot.entities.iter().flat_map(|e| &e.components).map(|c| &c.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(!ids.contains(&&handle_id) && !ids.contains(&&scratch_id));
+
+        // Save files go through the same snapshot path.
+        let bytes = encode_file(&snapshot, BinaryFormat::MessagePack, true).unwrap();
+        let mut restored = World::new();
+        restored.register_component::<Mesh>();
+        restored.register_component::<Position>();
+        restored.rebuild_transient::<GpuHandle, Mesh>();
+        let report = restored.restore_from_snapshot(&decode_file(&bytes).unwrap()).unwrap();
+        assert_eq!(report.components, 2);
+        assert_eq!(report.rebuilt, 1);
+        assert_eq!(restored.get_component::<GpuHandle>(ship), Some(&GpuHandle::default()));
+        assert!(restored.get_component::<GpuHandle>(marker).is_none());
+        assert!(!restored.has_component(ship, &scratch_id));
+
+        // Deltas never carry transient changes, and incoming ones are skipped.
+        let mut compressor = DeltaCompressor::new();
+        compressor.create_delta(&world);
+        world.add_component(ship, Box::new(GpuHandle { buffer: 7 }));
+        world.add_component(ship, Box::new(Scratch(vec![])));
+        assert!(compressor.create_delta(&world).changes.is_empty());
+
+        let mut sender = World::new();
+        sender.create_entity_with_id(ship);
+        let mut compressor = DeltaCompressor::new();
+        compressor.create_delta(&sender);
+        sender.add_component(ship, Box::new(GpuHandle { buffer: 99 }));
+        let report = restored.apply_delta(&compressor.create_delta(&sender));
+        assert_eq!(report.skipped, vec![(ship, handle_id.clone())]);
+        assert_eq!(restored.get_component::<GpuHandle>(ship), Some(&GpuHandle::default()));
+    }
+
+    #[test]
+    fn test_change_tracked_deltas() {
+        use crate::serialization::TrackedDeltaCompressor;
+        use crate::replication::{Relevance, Replicator};
+        use crate::system::{System, SystemPhase, SystemScheduler};
+        use crate::system_param::Query;
+        use std::collections::HashSet;
+        use tx2_link::DeltaChange;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Tag(String);
+
+        fn drift(mut query: Query<&mut Position>) {
+            query.for_each(|_, position| position.x += 1.0);
+        }
+
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Tag>();
+        let mut entities = Vec::new();
+        for i in 0..500 {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Position { x: i as f64, y: 0.0 }));
+            entities.push(entity);
+        }
+        let mut client = World::new();
+        client.register_component::<Position>();
+        client.register_component::<Tag>();
+
+        let assert_in_sync = |world: &World, client: &World| {
+            assert_eq!(world.get_all_entities().len(), client.get_all_entities().len());
+            for entity in world.get_all_entities() {
+                assert_eq!(client.get_component::<Position>(entity.id), world.get_component::<Position>(entity.id));
+                assert_eq!(client.get_component::<Tag>(entity.id), world.get_component::<Tag>(entity.id));
+            }
+        };
+
+        let mut compressor = TrackedDeltaCompressor::new();
+        let initial = compressor.create_delta(&mut world);
+        assert_eq!(initial.changes.len(), 1000);
+        assert!(!world.is_tracking_changes(), "the compressor keeps its own tracker");
+        assert!(client.apply_delta(&initial).is_clean());
+
+        // Nothing changed: nothing to send, however large the world.
+        assert!(compressor.create_delta(&mut world).changes.is_empty());
+
+        // One mutation produces one field-level change.
+        world.get_component_mut::<Position>(entities[3]).unwrap().y = 5.0;
+        let delta = compressor.create_delta(&mut world);
+        assert_eq!(delta.changes.len(), 1);
+        assert!(matches!(&delta.changes[0], DeltaChange::FieldsUpdated { fields, .. } if fields.len() == 1));
+        assert!(client.apply_delta(&delta).is_clean());
+
+        // Structural changes, including ones that cancel out within the window.
+        let spawned = world.create_entity().id;
+        world.add_component(spawned, Box::new(Tag("new".into())));
+        let short_lived = world.create_entity().id;
+        world.add_component(short_lived, Box::new(Tag("gone".into())));
+        world.destroy_entity(short_lived);
+        world.destroy_entity(entities[10]);
+        world.add_component(entities[20], Box::new(Tag("tagged".into())));
+        world.remove_component(entities[30], std::any::type_name::<Position>());
+        world.add_component(entities[40], Box::new(Tag("flicker".into())));
+        world.remove_component(entities[40], std::any::type_name::<Tag>());
+        let delta = compressor.create_delta(&mut world);
+        assert_eq!(delta.changes.len(), 5);
+        assert!(client.apply_delta(&delta).is_clean());
+        assert_in_sync(&world, &client);
+
+        // Writes through system queries are tracked too.
+        let mut scheduler = SystemScheduler::new();
+        scheduler.add(System::from_fn("drift", HashSet::from([SystemPhase::Update]), drift));
+        scheduler.execute_phase(SystemPhase::Update, &mut world, 0.016, 0.0).unwrap();
+        let delta = compressor.create_delta(&mut world);
+        assert_eq!(delta.changes.len(), 498);
+        assert!(client.apply_delta(&delta).is_clean());
+        assert_in_sync(&world, &client);
+
+        // Every consumer has its own tracker, so none steals another's changes.
+        let mut replicator = Replicator::new();
+        replicator.add_client(1, Relevance::all());
+        replicator.update(&mut world);
+        world.enable_change_tracking();
+        world.get_component_mut::<Position>(entities[5]).unwrap().x = -1.0;
+        assert_eq!(compressor.create_delta(&mut world).changes.len(), 1);
+        assert_eq!(replicator.update(&mut world)[&1].changes.len(), 1);
+        assert_eq!(world.take_changes().changed.len(), 1);
+        assert!(compressor.create_delta(&mut world).changes.is_empty());
+
+        // Without tracking the change set stays empty.
+        let mut untracked = World::new();
+        let entity = untracked.create_entity().id;
+        untracked.add_component(entity, Box::new(Position { x: 0.0, y: 0.0 }));
+        assert!(untracked.take_changes().is_empty());
+    }
+
+    #[test]
+    fn test_field_level_diffs() {
+        use crate::encoding::BinaryFormat;
+        use crate::patch;
+        use crate::serialization::TrackedDeltaCompressor;
+        use std::collections::BTreeMap;
+        use tx2_link::DeltaChange;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Item {
+            name: String,
+            count: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Inventory {
+            owner: String,
+            items: Vec<Item>,
+            tags: BTreeMap<String, String>,
+        }
+
+        // Diffs round-trip, including keys that need pointer escaping.
+        let old = serde_json::json!({"a": {"x/y": 1, "k~": [1, 2]}, "b": [1]});
+        let new = serde_json::json!({"a": {"x/y": 2, "k~": [1, 3, 4]}, "b": [], "c": true});
+        let fields = patch::diff(&old, &new).unwrap();
+        let pointers: Vec<&str> = fields.iter().map(|f| f.field_id.as_str()).collect();
+        assert!(pointers.contains(&"/a/x~1y") && pointers.contains(&"/a/k~0/1") && pointers.contains(&"/a/k~0/-"));
+        let mut patched = old.clone();
+        for field in &fields {
+            patch::apply(&mut patched, field).unwrap();
+        }
+        assert_eq!(patched, new);
+        assert!(patch::diff(&new, &old).is_none(), "a lost root key needs a full update");
+
+        let inventory = Inventory {
+            owner: "player-one".into(),
+            items: (0..40).map(|i| Item { name: format!("item-{}", i), count: i }).collect(),
+            tags: BTreeMap::from([("guild".to_string(), "north".to_string())]),
+        };
+
+        for format in [BinaryFormat::Json, BinaryFormat::MessagePack, BinaryFormat::Bincode] {
+            let mut world = World::new();
+            world.register_component::<Inventory>();
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(inventory.clone()));
+            let mut client = World::new();
+            client.register_component::<Inventory>();
+
+            let mut tracked = TrackedDeltaCompressor::with_format(format);
+            let mut snapshots = DeltaCompressor::with_format(format);
+            let initial = tracked.create_delta(&mut world);
+            snapshots.create_delta(&world);
+            assert!(client.apply_delta_with(&initial, format).is_clean());
+
+            let edits: [fn(&mut Inventory); 3] = [
+                |inv| inv.items[7].count += 5,
+                |inv| inv.items.push(Item { name: "relic".into(), count: 1 }),
+                |inv| { inv.tags.insert("rank".into(), "gold".into()); },
+            ];
+            for edit in edits {
+                edit(world.get_component_mut::<Inventory>(entity).unwrap());
+                let delta = tracked.create_delta(&mut world);
+                let from_snapshots = snapshots.create_delta(&world);
+
+                assert_eq!(delta.changes.len(), 1);
+                let DeltaChange::FieldsUpdated { fields, .. } = &delta.changes[0] else {
+                    panic!("expected a field patch in {:?}, got {:?}", format, delta.changes[0]);
+                };
+                assert!(fields.len() <= 2 && fields.iter().all(|f| patch::is_pointer(&f.field_id)));
+                let patched_size = serde_json::to_vec(&delta).unwrap().len();
+                let full_size = serde_json::to_vec(world.get_component::<Inventory>(entity).unwrap()).unwrap().len();
+                assert!(patched_size * 4 < full_size, "{} vs {}", patched_size, full_size);
+
+                // Bincode snapshots cannot be read back untyped, so that path sends whole components.
+                let patched = matches!(from_snapshots.changes[0], DeltaChange::FieldsUpdated { .. });
+                assert_eq!(patched, format != BinaryFormat::Bincode);
+
+                assert!(client.apply_delta_with(&delta, format).is_clean());
+                assert_eq!(client.get_component::<Inventory>(entity), world.get_component::<Inventory>(entity));
+            }
+
+            // Shrinking an array replaces it at its pointer.
+            world.get_component_mut::<Inventory>(entity).unwrap().items.truncate(10);
+            let delta = tracked.create_delta(&mut world);
+            assert!(client.apply_delta_with(&delta, format).is_clean());
+            assert_eq!(client.get_component::<Inventory>(entity), world.get_component::<Inventory>(entity));
+        }
+    }
+
+    #[test]
+    fn test_quantized_replication() {
+        use crate::encoding::BinaryFormat;
+        use crate::quantize::{Quantization, f16_bits_to_f32, f32_to_f16_bits};
+        use crate::replication::{Relevance, ReplicationReceiver, Replicator};
+        use crate::serialization::{SnapshotOptions, TrackedDeltaCompressor};
+        use tx2_link::protocol::ComponentData;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Transform {
+            position: [f64; 3],
+            angle: f64,
+            scale: f32,
+        }
+
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(-2.5), 0xc100);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16_bits(1e6), 0x7c00);
+        assert_eq!(f16_bits_to_f32(f32_to_f16_bits(5.960_464_5e-8)), 5.960_464_5e-8);
+        assert_eq!(f16_bits_to_f32(0x3555), 0.333_251_95);
+
+        let register = |world: &mut World| {
+            world.register_component::<Transform>()
+                .with_quantization("/position", Quantization::fixed(0.01))
+                .with_quantization("/angle", Quantization::range(-std::f64::consts::PI, std::f64::consts::PI, 12))
+                .with_quantization("/scale", Quantization::Half);
+        };
+        let transform = |i: usize| Transform {
+            position: [i as f64 * 1.2345, -(i as f64) * 0.6789, 100.0 + i as f64 / 7.0],
+            angle: (i as f64 * 0.37) % std::f64::consts::PI,
+            scale: 1.0 + i as f32 / 1000.0,
+        };
+        let close = |a: &Transform, b: &Transform| {
+            a.position.iter().zip(&b.position).all(|(x, y)| (x - y).abs() <= 0.005 + 1e-9)
+                && (a.angle - b.angle).abs() <= Quantization::range(-3.2, 3.2, 12).max_error()
+                && (a.scale - b.scale).abs() <= a.scale / 1024.0
+        };
+
+        let mut plain = World::new();
+        plain.register_component::<Transform>();
+        let mut world = World::new();
+        register(&mut world);
+        let mut entities = Vec::new();
+        for i in 0..1000 {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(transform(i)));
+            plain.create_entity_with_id(entity);
+            plain.add_component(entity, Box::new(transform(i)));
+            entities.push(entity);
+        }
+
+        let payload = |snapshot: &tx2_link::WorldSnapshot| -> usize {
+            snapshot.entities.iter().flat_map(|e| &e.components).map(|c| match &c.data {
+                ComponentData::Json(json) => json.len(),
+                ComponentData::Binary(bytes) => bytes.len(),
+                ComponentData::Structured(_) => 0,
+            }).sum()
+        };
+        for format in [BinaryFormat::Json, BinaryFormat::MessagePack, BinaryFormat::Bincode] {
+            let options = SnapshotOptions::new().with_format(format).with_quantization(true);
+            let quantized = world.create_snapshot_with(&options);
+            let full = payload(&plain.create_snapshot_with(&options));
+            println!("{:?}: {} bytes quantized, {} bytes full", format, payload(&quantized), full);
+            if format == BinaryFormat::Bincode {
+                assert_eq!(payload(&quantized), full);
+            } else {
+                assert!(payload(&quantized) * 3 < full * 2, "{:?}", format);
+            }
+
+            let mut restored = World::new();
+            register(&mut restored);
+            restored.restore_from_snapshot(&quantized).unwrap();
+            for entity in &entities {
+                let original = world.get_component::<Transform>(*entity).unwrap();
+                assert!(close(original, restored.get_component::<Transform>(*entity).unwrap()));
+            }
+        }
+
+        // Plain snapshots and save files keep exact values.
+        let exact = |restored: &World| entities.iter().all(|entity| {
+            restored.get_component::<Transform>(*entity) == world.get_component::<Transform>(*entity)
+        });
+        let mut restored = World::new();
+        register(&mut restored);
+        let snapshot = world.create_snapshot_with(&SnapshotOptions::new().with_format(BinaryFormat::MessagePack));
+        restored.restore_from_snapshot(&snapshot).unwrap();
+        assert!(exact(&restored));
+        let path = std::env::temp_dir().join(format!("tx2-quantized-{}.tx2", std::process::id()));
+        world.save_to(&path).unwrap();
+        let mut loaded = World::new();
+        register(&mut loaded);
+        loaded.load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(exact(&loaded));
+
+        // Deltas: changes below the step are not sent, patches carry quantized values.
+        let mut client = World::new();
+        register(&mut client);
+        let mut compressor = TrackedDeltaCompressor::with_format(BinaryFormat::MessagePack);
+        assert!(client.apply_delta_with(&compressor.create_delta(&mut world), BinaryFormat::MessagePack).is_clean());
+
+        world.get_component_mut::<Transform>(entities[0]).unwrap().position[0] += 0.001;
+        assert!(compressor.create_delta(&mut world).changes.is_empty());
+
+        world.get_component_mut::<Transform>(entities[1]).unwrap().position[2] += 3.0;
+        world.get_component_mut::<Transform>(entities[2]).unwrap().angle = -1.0;
+        let delta = compressor.create_delta(&mut world);
+        assert_eq!(delta.changes.len(), 2);
+        assert!(client.apply_delta_with(&delta, BinaryFormat::MessagePack).is_clean());
+        for entity in &entities {
+            assert!(close(world.get_component::<Transform>(*entity).unwrap(), client.get_component::<Transform>(*entity).unwrap()));
+        }
+
+        // Replicated deltas name their format, so a fresh receiver decodes binary full state.
+        let mut replicator = Replicator::with_options(SnapshotOptions::new().with_format(BinaryFormat::MessagePack).with_quantization(true));
+        replicator.add_client(1, Relevance::all());
+        let mut client = World::new();
+        register(&mut client);
+        let full = replicator.update(&mut world).remove(&1).unwrap();
+        assert_eq!(full.format, BinaryFormat::MessagePack);
+        assert!(ReplicationReceiver::new().apply(&mut client, &full).unwrap().is_clean());
+        for entity in &entities {
+            assert!(close(world.get_component::<Transform>(*entity).unwrap(), client.get_component::<Transform>(*entity).unwrap()));
+        }
+
+        // Integers are never quantized, so a wildcard over a mixed struct is refused.
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Marker {
+            x: f32,
+            y: f32,
+            id: u32,
+        }
+        let marked = |pointers: &[&str]| {
+            let mut world = World::new();
+            let registration = world.register_component::<Marker>();
+            for pointer in pointers {
+                registration.with_quantization(pointer, Quantization::fixed(0.01));
+            }
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Marker { x: 1.25, y: -0.5, id: 7 }));
+            (world, entity)
+        };
+        let options = SnapshotOptions::new().with_format(BinaryFormat::MessagePack).with_quantization(true);
+        let (wildcard, _) = marked(&["/*"]);
+        let refused = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| wildcard.create_snapshot_with(&options)));
+        assert!(refused.is_err());
+        let (fields, entity) = marked(&["/x", "/y"]);
+        let (mut restored, _) = marked(&["/x", "/y"]);
+        restored.restore_from_snapshot(&fields.create_snapshot_with(&options)).unwrap();
+        assert_eq!(restored.get_component::<Marker>(entity), Some(&Marker { x: 1.25, y: -0.5, id: 7 }));
+
+        // Quantization is part of the schema peers compare.
+        assert_ne!(world.component_registry().schema_version(), plain.component_registry().schema_version());
+    }
+
+    #[test]
+    fn test_per_client_replication() {
+        use crate::entity::EntityId;
+        use crate::replication::{Relevance, Replicator};
+        use tx2_link::DeltaChange;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Team(String);
+
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Team>();
+        let spawn = |world: &mut World, x: f64, team: &str| {
+            let entity = world.create_entity().id;
+            world.add_component(entity, Box::new(Position { x, y: 0.0 }));
+            world.add_component(entity, Box::new(Team(team.to_string())));
+            entity
+        };
+        let viewer = spawn(&mut world, 0.0, "blue");
+        let near = spawn(&mut world, 5.0, "red");
+        let far = spawn(&mut world, 50.0, "red");
+        let chest = spawn(&mut world, 100.0, "none");
+
+        let mut replicator = Replicator::new();
+        replicator.add_client(1, Relevance::new(|world, entity| {
+            world.get_component::<Team>(entity).is_some_and(|team| team.0 == "red")
+        }));
+        replicator.add_client(2, Relevance::within_distance::<Position>(viewer, 10.0, |p| [p.x, p.y, 0.0]));
+        replicator.add_client(3, Relevance::none());
+        replicator.subscribe(3, chest);
+
+        let mut clients: Vec<World> = (0..3).map(|_| {
+            let mut client = World::new();
+            client.register_component::<Position>();
+            client.register_component::<Team>();
+            client
+        }).collect();
+        let sync = |replicator: &mut Replicator, world: &mut World, clients: &mut Vec<World>| {
+            let deltas = replicator.update(world);
+            for (client, delta) in &deltas {
+                let report = clients[*client as usize - 1].apply_delta(delta);
+                assert!(report.is_clean(), "client {}: {:?}", client, report.mismatches);
+            }
+            deltas
+        };
+        let held = |client: &World| -> Vec<EntityId> {
+            let mut ids: Vec<EntityId> = client.get_all_entities().iter().map(|e| e.id).collect();
+            ids.sort();
+            ids
+        };
+
+        sync(&mut replicator, &mut world, &mut clients);
+        assert_eq!(held(&clients[0]), vec![near, far]);
+        assert_eq!(held(&clients[1]), vec![viewer, near]);
+        assert_eq!(held(&clients[2]), vec![chest]);
+        assert_eq!(replicator.replicated_entities(2), vec![viewer, near]);
+
+        // A change reaches only the clients that hold the entity.
+        world.get_component_mut::<Position>(far).unwrap().y = 1.0;
+        let deltas = sync(&mut replicator, &mut world, &mut clients);
+        assert_eq!(deltas[&1].changes.len(), 1);
+        assert!(deltas[&2].changes.is_empty() && deltas[&3].changes.is_empty());
+
+        // The viewer moves: `near` leaves relevance, `far` enters with full state.
+        world.get_component_mut::<Position>(viewer).unwrap().x = 45.0;
+        let deltas = sync(&mut replicator, &mut world, &mut clients);
+        assert!(deltas[&2].changes.iter().any(|c| matches!(c, DeltaChange::EntityRemoved { entity_id } if *entity_id == near)));
+        assert!(deltas[&2].changes.iter().any(|c| matches!(c, DeltaChange::EntityAdded { entity_id } if *entity_id == far)));
+        assert_eq!(held(&clients[1]), vec![viewer, far]);
+        assert_eq!(clients[1].get_component::<Position>(far), world.get_component::<Position>(far));
+
+        // Team changes move entities in and out of the first client's view.
+        world.add_component(chest, Box::new(Team("red".into())));
+        world.remove_component(chest, std::any::type_name::<Team>());
+        world.add_component(chest, Box::new(Team("red".into())));
+        world.destroy_entity(near);
+        sync(&mut replicator, &mut world, &mut clients);
+        assert_eq!(held(&clients[0]), vec![far, chest]);
+
+        replicator.unsubscribe(3, chest);
+        replicator.subscribe(3, viewer);
+        sync(&mut replicator, &mut world, &mut clients);
+        assert_eq!(held(&clients[2]), vec![viewer]);
+
+        // Every client holds exactly the world's state for what it can see.
+        for client in &clients {
+            for entity in held(client) {
+                assert_eq!(client.get_component::<Position>(entity), world.get_component::<Position>(entity));
+                assert_eq!(client.get_component::<Team>(entity), world.get_component::<Team>(entity));
+            }
+        }
+
+        // A late joiner starts from full state.
+        replicator.add_client(4, Relevance::all());
+        let deltas = replicator.update(&mut world);
+        assert_eq!(deltas[&4].base_timestamp, 0.0);
+        assert_eq!(deltas[&4].changes.iter().filter(|c| matches!(c, DeltaChange::EntityAdded { .. })).count(), 3);
+    }
+
+    #[test]
+    fn test_acknowledged_replication() {
+        use crate::replication::{Relevance, ReplicationReceiver, Replicator};
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Label(String);
+
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Label>();
+        let a = world.create_entity().id;
+        world.add_component(a, Box::new(Position { x: 1.0, y: 0.0 }));
+        let b = world.create_entity().id;
+        world.add_component(b, Box::new(Position { x: 2.0, y: 0.0 }));
+
+        let mut replicator = Replicator::new().with_history(4);
+        replicator.add_unreliable_client(1, Relevance::all());
+        let mut client = World::new();
+        client.register_component::<Position>();
+        client.register_component::<Label>();
+        let mut receiver = ReplicationReceiver::new();
+        let converged = |world: &World, client: &World| {
+            let mut ids: Vec<_> = world.get_all_entities().iter().map(|e| e.id).collect();
+            let mut held: Vec<_> = client.get_all_entities().iter().map(|e| e.id).collect();
+            ids.sort();
+            held.sort();
+            ids == held && ids.iter().all(|id| {
+                world.get_component::<Position>(*id) == client.get_component::<Position>(*id)
+                    && world.get_component::<Label>(*id) == client.get_component::<Label>(*id)
+            })
+        };
+
+        // Until something is acknowledged, deltas build on the full state.
+        let first = replicator.update(&mut world).remove(&1).unwrap();
+        let second = replicator.update(&mut world).remove(&1).unwrap();
+        assert!(first.is_full());
+        assert_eq!(second.base, Some(first.sequence));
+        assert!(receiver.apply(&mut client, &second).is_none(), "applied without its base");
+        assert!(receiver.apply(&mut client, &first).is_some());
+        assert!(replicator.acknowledge(1, receiver.applied().unwrap()));
+        assert!(!replicator.acknowledge(1, first.sequence));
+        assert!(!replicator.acknowledge(1, 99));
+        assert!(receiver.apply(&mut client, &second).is_some());
+        assert!(converged(&world, &client));
+
+        // Lost: a label added, then changed back and forth.
+        world.add_component(a, Box::new(Label("x".into())));
+        let lost = replicator.update(&mut world).remove(&1).unwrap();
+        assert_eq!(lost.base, Some(first.sequence));
+        // Delivered, but its acknowledgement is lost.
+        world.get_component_mut::<Position>(b).unwrap().x = 3.0;
+        world.remove_component(a, std::any::type_name::<Label>());
+        let unacked = replicator.update(&mut world).remove(&1).unwrap();
+        assert!(receiver.apply(&mut client, &unacked).is_some());
+        // Delivered out of order after its successor: dropped.
+        let c = world.create_entity().id;
+        world.add_component(c, Box::new(Position { x: 4.0, y: 0.0 }));
+        world.add_component(a, Box::new(Label("y".into())));
+        let late = replicator.update(&mut world).remove(&1).unwrap();
+        world.destroy_entity(b);
+        let next = replicator.update(&mut world).remove(&1).unwrap();
+        assert_eq!(next.base, Some(first.sequence));
+        assert!(receiver.apply(&mut client, &next).is_some());
+        assert!(receiver.apply(&mut client, &late).is_none());
+        assert!(converged(&world, &client));
+        assert!(replicator.acknowledge(1, next.sequence));
+
+        // Restated changes are gone once acknowledged.
+        world.get_component_mut::<Position>(c).unwrap().y = 1.0;
+        let delta = replicator.update(&mut world).remove(&1).unwrap();
+        assert_eq!(delta.base, Some(next.sequence));
+        assert_eq!(delta.changes.len(), 1);
+        let report = receiver.apply(&mut client, &delta).unwrap();
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert!(replicator.acknowledge(1, delta.sequence));
+
+        // Falling further behind than the history falls back to full state.
+        let mut dropped = Vec::new();
+        for step in 0..5 {
+            world.get_component_mut::<Position>(a).unwrap().x = step as f64;
+            dropped.push(replicator.update(&mut world).remove(&1).unwrap());
+        }
+        assert!(dropped[..4].iter().all(|d| d.base == Some(delta.sequence)));
+        world.destroy_entity(c);
+        let full = replicator.update(&mut world).remove(&1).unwrap();
+        assert!(full.is_full());
+        assert!(receiver.apply(&mut client, &full).unwrap().is_clean());
+        assert!(converged(&world, &client));
+    }
+
+    #[test]
+    fn test_replication_under_packet_loss() {
+        use crate::entity::EntityId;
+        use crate::replication::{Relevance, ReplicationReceiver, Replicator, SequencedDelta};
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Tag(u32);
+
+        type State = BTreeMap<EntityId, (Option<Position>, Option<Tag>)>;
+        let state = |world: &World| -> State {
+            world.get_all_entities().iter().map(|e| {
+                (e.id, (world.get_component::<Position>(e.id).cloned(), world.get_component::<Tag>(e.id).cloned()))
+            }).collect()
+        };
+
+        for seed in 1..=20u64 {
+            // xorshift64, so runs are reproducible without a rand dependency.
+            let mut rng = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            let mut next = move |n: u64| {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                rng % n
+            };
+
+            let mut world = World::new();
+            world.register_component::<Position>();
+            world.register_component::<Tag>();
+            let mut replicator = Replicator::new().with_history(8);
+            replicator.add_unreliable_client(1, Relevance::new(|world, entity| {
+                world.get_component::<Position>(entity).is_some_and(|p| p.x < 8.0)
+            }));
+            let mut client = World::new();
+            client.register_component::<Position>();
+            client.register_component::<Tag>();
+            let mut receiver = ReplicationReceiver::new();
+
+            let mut sent: BTreeMap<u64, State> = BTreeMap::new();
+            let mut to_client: Vec<(u64, SequencedDelta)> = Vec::new();
+            let mut to_server: Vec<(u64, u64)> = Vec::new();
+            let mut entities: Vec<EntityId> = Vec::new();
+            let mut applied = 0;
+
+            for tick in 0..300u64 {
+                // Lossless for the last stretch, so the client must catch up.
+                let lossy = tick < 260;
+                for _ in 0..next(4) {
+                    match next(6) {
+                        0 => {
+                            let entity = world.create_entity().id;
+                            world.add_component(entity, Box::new(Position { x: next(10) as f64, y: 0.0 }));
+                            entities.push(entity);
+                        }
+                        1 if !entities.is_empty() => {
+                            let entity = entities.swap_remove(next(entities.len() as u64) as usize);
+                            world.destroy_entity(entity);
+                        }
+                        2 | 3 if !entities.is_empty() => {
+                            let entity = entities[next(entities.len() as u64) as usize];
+                            world.add_component(entity, Box::new(Tag(next(100) as u32)));
+                        }
+                        4 if !entities.is_empty() => {
+                            let entity = entities[next(entities.len() as u64) as usize];
+                            world.remove_component(entity, std::any::type_name::<Tag>());
+                        }
+                        _ if !entities.is_empty() => {
+                            let entity = entities[next(entities.len() as u64) as usize];
+                            world.get_component_mut::<Position>(entity).unwrap().x = next(10) as f64;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let delta = replicator.update(&mut world).remove(&1).unwrap();
+                let expected: State = state(&world).into_iter()
+                    .filter(|(_, (position, _))| position.as_ref().is_some_and(|p| p.x < 8.0))
+                    .collect();
+                sent.insert(delta.sequence, expected);
+                if !lossy || next(10) >= 3 {
+                    to_client.push((tick + next(if lossy { 4 } else { 1 }), delta));
+                }
+
+                // Delivery in arrival order; equal delays arrive in any order.
+                let (arrived, pending): (Vec<_>, Vec<_>) = to_client.drain(..).partition(|(at, _)| *at <= tick);
+                to_client = pending;
+                let mut arrived: Vec<_> = arrived.into_iter().map(|(_, delta)| delta).collect();
+                if arrived.len() > 1 && next(2) == 0 {
+                    arrived.reverse();
+                }
+                for delta in arrived {
+                    if receiver.apply(&mut client, &delta).is_some() {
+                        applied += 1;
+                        assert_eq!(state(&client), sent[&delta.sequence], "seed {} seq {}", seed, delta.sequence);
+                        if !lossy || next(10) >= 3 {
+                            to_server.push((tick + next(if lossy { 5 } else { 1 }), delta.sequence));
+                        }
+                    }
+                }
+
+                let (acks, pending): (Vec<_>, Vec<_>) = to_server.drain(..).partition(|(at, _)| *at <= tick);
+                to_server = pending;
+                for (_, sequence) in acks {
+                    replicator.acknowledge(1, sequence);
+                }
+            }
+            assert!(applied > 100, "seed {}: only {} deltas applied", seed, applied);
+            assert_eq!(receiver.applied(), sent.keys().last().copied(), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_versioned_replication() {
+        use crate::replication::{Relevance, ReplicationReceiver, Replicator};
+        use serde_json::{Value, json};
+        use tx2_link::DeltaChange;
+
+        // v1 was `{ "hp": n }`; both peers run v2.
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+        struct Hp {
+            health: u32,
+            max: u32,
+        }
+
+        fn rename_hp(mut value: Value) -> Result<Value, String> {
+            let hp = value.as_object_mut().and_then(|o| o.remove("hp")).ok_or("missing hp")?;
+            Ok(json!({ "health": hp, "max": hp }))
+        }
+
+        let peer = || {
+            let mut world = World::new();
+            world.register_component::<Hp>().with_version(2).with_migration(1, rename_hp);
+            world
+        };
+
+        // Without a restore, deltas are taken to be written at the registered version.
+        let mut world = peer();
+        let id = world.create_entity().id;
+        world.add_component(id, Box::new(Hp { health: 40, max: 50 }));
+        let mut client = peer();
+        let report = client.apply_delta(&DeltaCompressor::new().create_delta(&world));
+        assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+        assert_eq!(client.get_component::<Hp>(id), Some(&Hp { health: 40, max: 50 }));
+
+        // Replicated field updates carry the sender's versions and apply cleanly.
+        let mut replicator = Replicator::new();
+        replicator.add_client(1, Relevance::all());
+        let mut client = peer();
+        let mut receiver = ReplicationReceiver::new();
+        let full = replicator.update(&mut world).remove(&1).unwrap();
+        assert_eq!(full.versions.get(std::any::type_name::<Hp>()), Some(&2));
+        receiver.apply(&mut client, &full).unwrap();
+        world.get_component_mut::<Hp>(id).unwrap().health = 25;
+        let delta = replicator.update(&mut world).remove(&1).unwrap();
+        assert!(delta.changes.iter().any(|c| matches!(c, DeltaChange::FieldsUpdated { .. })));
+        let report = receiver.apply(&mut client, &delta).unwrap();
+        assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+        assert_eq!(client.get_component::<Hp>(id), Some(&Hp { health: 25, max: 50 }));
+    }
 }
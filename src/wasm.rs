@@ -3,7 +3,8 @@ use serde::{Serialize, Deserialize};
 use tsify::Tsify;
 use crate::entity::{Entity, EntityId};
 use crate::world::World;
-use crate::component::{Component, ComponentId};
+use crate::system::SystemScheduler;
+use crate::component::{Component, ComponentId, DynamicComponent};
 use std::collections::HashMap;
 
 #[wasm_bindgen(start)]
@@ -73,6 +74,7 @@ pub struct WasmSerializedComponent {
 #[wasm_bindgen]
 pub struct WasmWorld {
     inner: World,
+    scheduler: SystemScheduler,
 }
 
 #[wasm_bindgen]
@@ -81,6 +83,7 @@ impl WasmWorld {
     pub fn new() -> WasmWorld {
         WasmWorld {
             inner: World::new(),
+            scheduler: SystemScheduler::new(),
         }
     }
 
@@ -173,9 +176,15 @@ impl WasmWorld {
             .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    #[wasm_bindgen(js_name = markTransient)]
+    pub fn mark_transient(&mut self, component_id: String) {
+        self.inner.component_registry_mut().set_transient(component_id);
+    }
+
     #[wasm_bindgen(js_name = createSnapshot)]
     pub fn create_snapshot(&self) -> Result<JsValue, JsValue> {
         let entities = self.inner.get_all_entities();
+        let registry = self.inner.component_registry();
 
         let serialized_entities: Vec<WasmSerializedEntity> = entities
             .into_iter()
@@ -183,6 +192,7 @@ impl WasmWorld {
                 let components = self.inner.get_all_components(entity.id);
                 let serialized_components: Vec<WasmSerializedComponent> = components
                     .into_iter()
+                    .filter(|c| !registry.is_transient(&c.component_id()))
                     .map(|c| WasmSerializedComponent {
                         id: c.component_id(),
                         data: c.to_json(),
@@ -232,6 +242,33 @@ impl WasmWorld {
         self.inner.clear();
     }
 
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self, real_dt: f64) -> Result<(), JsValue> {
+        self.inner
+            .run_frame(&mut self.scheduler, real_dt)
+            .map(|_| ())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[wasm_bindgen(js_name = getSystemStats)]
+    pub fn get_system_stats(&self) -> Result<JsValue, JsValue> {
+        // Flattened timing fields serialize as a map; emit plain objects instead of JS Maps.
+        self.scheduler
+            .profile_report()
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[wasm_bindgen(js_name = getSystemStatsJson)]
+    pub fn get_system_stats_json(&self) -> String {
+        self.scheduler.export_profile_json()
+    }
+
+    #[wasm_bindgen(js_name = resetSystemStats)]
+    pub fn reset_system_stats(&mut self) {
+        self.scheduler.reset_stats();
+    }
+
     #[wasm_bindgen(js_name = query)]
     pub fn query(&mut self, include_components: Vec<String>, exclude_components: Vec<String>) -> Result<JsValue, JsValue> {
         use crate::query::QueryDescriptor;
@@ -249,31 +286,14 @@ impl WasmWorld {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct DynamicComponent {
-    id: String,
-    data: serde_json::Value,
-}
-
-impl Component for DynamicComponent {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
-
-    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
-        self
-    }
-
-    fn component_id(&self) -> ComponentId {
-        self.id.clone()
-    }
-
-    fn clone_box(&self) -> Box<dyn Component> {
-        Box::new(self.clone())
+impl WasmWorld {
+    // Systems are registered from Rust; JS drives them through `runFrame`.
+    pub fn scheduler_mut(&mut self) -> &mut SystemScheduler {
+        &mut self.scheduler
     }
 
-    fn to_json(&self) -> serde_json::Value {
-        self.data.clone()
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.inner
     }
 }
 
@@ -1,18 +1,51 @@
-use std::collections::HashMap;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use crate::entity::{Entity, EntityId, create_entity_id};
-use crate::component::{Component, ComponentStore, ComponentId};
+use crate::component::{ChangeSet, ChangeTracker, Component, ComponentStore, ComponentId, ComponentRegistration, ComponentRegistry, UnknownComponentPolicy};
+use crate::encoding::BinaryFormat;
+use crate::persistence::{self, SaveOptions};
+use crate::serialization::{DeltaApplyReport, RestoreReport, Serializer, SnapshotFilter, SnapshotOptions};
 use crate::query::{Query, QueryBuilder, QueryCache, QueryDescriptor};
+use crate::error::TX2Error;
+use crate::event::{self, Events};
+use crate::system::{SystemCommands, SystemPhase, SystemScheduler};
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use tx2_link::{Delta, WorldSnapshot};
+
+// Timing for a single frame, as computed by `World::step`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTime {
+    pub frame: u64,
+    pub delta_time: f64,
+    pub time: f64,
+    pub fixed_delta: f64,
+    pub fixed_steps: u32,
+    pub fixed_time: f64,
+    pub alpha: f64,
+}
 
 pub struct World {
     pub(crate) entities: HashMap<EntityId, Entity>,
     pub(crate) component_store: ComponentStore,
     pub(crate) query_cache: QueryCache,
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    component_registry: ComponentRegistry,
+    system_commands: SystemCommands,
+    event_updaters: HashMap<TypeId, fn(&mut World)>,
     time: f64,
     fixed_time: f64,
     accumulator: f64,
     running: bool,
     paused: bool,
+    step_requested: bool,
+    fixed_timestep: f64,
+    time_scale: f64,
+    max_frame_time: f64,
+    max_fixed_steps: u32,
+    frame: u64,
+    alpha: f64,
 }
 
 impl World {
@@ -21,17 +54,29 @@ impl World {
             entities: HashMap::new(),
             component_store: ComponentStore::new(),
             query_cache: QueryCache::new(),
+            resources: HashMap::new(),
+            component_registry: ComponentRegistry::new(),
+            system_commands: SystemCommands::default(),
+            event_updaters: HashMap::new(),
             time: 0.0,
             fixed_time: 0.0,
             accumulator: 0.0,
             running: false,
             paused: false,
+            step_requested: false,
+            fixed_timestep: 1.0 / 60.0,
+            time_scale: 1.0,
+            max_frame_time: 0.25,
+            max_fixed_steps: 8,
+            frame: 0,
+            alpha: 0.0,
         }
     }
 
     pub fn create_entity(&mut self) -> Entity {
         let entity = Entity::new();
         self.entities.insert(entity.id, entity);
+        self.component_store.record_spawned(entity.id);
         self.query_cache.mark_all_dirty();
         entity
     }
@@ -42,6 +87,7 @@ impl World {
         }
         let entity = Entity::with_id(id);
         self.entities.insert(id, entity);
+        self.component_store.record_spawned(id);
         self.query_cache.mark_all_dirty();
         entity
     }
@@ -49,6 +95,7 @@ impl World {
     pub fn destroy_entity(&mut self, entity_id: EntityId) -> bool {
         if self.entities.remove(&entity_id).is_some() {
             self.component_store.remove_all_components(entity_id);
+            self.component_store.record_despawned(entity_id);
             self.query_cache.mark_all_dirty();
             return true;
         }
@@ -88,6 +135,11 @@ impl World {
         self.component_store.get::<T>(entity_id)
     }
 
+    // Marks the component changed when change tracking is enabled.
+    pub fn get_component_mut<T: Component>(&mut self, entity_id: EntityId) -> Option<&mut T> {
+        self.component_store.get_mut::<T>(entity_id)
+    }
+
     pub fn get_all_components(&self, entity_id: EntityId) -> Vec<&Box<dyn Component>> {
         self.component_store.get_all(entity_id)
     }
@@ -105,9 +157,330 @@ impl World {
         QueryBuilder::new()
     }
 
+    // Records entity and component changes from now on; see `take_changes`.
+    pub fn enable_change_tracking(&mut self) {
+        self.component_store.enable_change_tracking();
+    }
+
+    pub fn disable_change_tracking(&mut self) {
+        self.component_store.disable_change_tracking();
+    }
+
+    pub fn is_tracking_changes(&self) -> bool {
+        self.component_store.is_tracking_changes()
+    }
+
+    pub fn changes(&self) -> Option<&ChangeSet> {
+        self.component_store.changes()
+    }
+
+    pub fn take_changes(&mut self) -> ChangeSet {
+        self.component_store.take_changes()
+    }
+
+    // A separate change set for one consumer, such as a delta compressor or
+    // a replicator, so consumers do not drain each other's changes.
+    pub fn track_changes(&mut self) -> ChangeTracker {
+        self.component_store.track_changes()
+    }
+
+    pub fn untrack_changes(&mut self, tracker: ChangeTracker) {
+        self.component_store.untrack_changes(tracker);
+    }
+
+    pub fn take_changes_for(&mut self, tracker: ChangeTracker) -> Option<ChangeSet> {
+        self.component_store.take_changes_for(tracker)
+    }
+
+    pub fn mark_changed<T: Component>(&mut self, entity_id: EntityId) {
+        self.component_store.mark_changed(entity_id, std::any::type_name::<T>());
+    }
+
+    pub fn register_component<T: Component + DeserializeOwned>(&mut self) -> &mut ComponentRegistration {
+        self.component_registry.register::<T>()
+    }
+
+    pub fn component_registry(&self) -> &ComponentRegistry {
+        &self.component_registry
+    }
+
+    pub fn component_registry_mut(&mut self) -> &mut ComponentRegistry {
+        &mut self.component_registry
+    }
+
+    // Keeps `T` out of snapshots, deltas and save files.
+    pub fn mark_transient<T: Component>(&mut self) {
+        self.component_registry.set_transient(std::any::type_name::<T>());
+    }
+
+    // Marks `T` transient and recreates it from its default on restored
+    // entities that carry `Trigger`.
+    pub fn rebuild_transient<T: Component + Default, Trigger: Component>(&mut self) {
+        self.component_registry.rebuild_with_default::<T>(Some(std::any::type_name::<Trigger>().to_string()));
+    }
+
+    pub fn set_unknown_component_policy(&mut self, policy: UnknownComponentPolicy) {
+        self.component_registry.set_unknown_policy(policy);
+    }
+
+    pub fn create_snapshot(&self) -> WorldSnapshot {
+        Serializer::create_snapshot(self)
+    }
+
+    pub fn create_snapshot_with(&self, options: &SnapshotOptions) -> WorldSnapshot {
+        Serializer::create_snapshot_with(self, options)
+    }
+
+    pub fn create_snapshot_filtered(&self, filter: &SnapshotFilter) -> WorldSnapshot {
+        Serializer::create_snapshot_filtered(self, filter)
+    }
+
+    pub fn create_snapshot_with_metadata(&self, metadata: BTreeMap<String, String>) -> WorldSnapshot {
+        Serializer::create_snapshot_with_metadata(self, metadata)
+    }
+
+    // Replaces all entities with those in the snapshot, rebuilding typed
+    // components through the component registry.
+    pub fn restore_from_snapshot(&mut self, snapshot: &WorldSnapshot) -> Result<RestoreReport, TX2Error> {
+        let policy = self.component_registry.unknown_policy();
+        Serializer::restore_snapshot(self, snapshot, policy)
+    }
+
+    pub fn restore_from_snapshot_with(
+        &mut self,
+        snapshot: &WorldSnapshot,
+        policy: UnknownComponentPolicy,
+    ) -> Result<RestoreReport, TX2Error> {
+        Serializer::restore_snapshot(self, snapshot, policy)
+    }
+
+    // Applies a tx2-link delta; changes whose preconditions fail are listed
+    // in the report instead of panicking.
+    pub fn apply_delta(&mut self, delta: &Delta) -> DeltaApplyReport {
+        Serializer::apply_delta(self, delta)
+    }
+
+    pub fn apply_delta_with(&mut self, delta: &Delta, format: BinaryFormat) -> DeltaApplyReport {
+        Serializer::apply_delta_with(self, delta, format)
+    }
+
+    // Writes the world to `path` atomically, as compressed MessagePack.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), TX2Error> {
+        persistence::save_world(self, path.as_ref(), &SaveOptions::new())
+    }
+
+    pub fn save_to_with<P: AsRef<Path>>(&self, path: P, options: &SaveOptions) -> Result<(), TX2Error> {
+        persistence::save_world(self, path.as_ref(), options)
+    }
+
+    pub fn load_from<P: AsRef<Path>>(&mut self, path: P) -> Result<RestoreReport, TX2Error> {
+        let snapshot = persistence::read_snapshot(path.as_ref())?;
+        self.restore_from_snapshot(&snapshot)
+    }
+
+    pub fn insert_resource<R: Any + Send + Sync>(&mut self, resource: R) -> Option<R> {
+        self.resources
+            .insert(TypeId::of::<R>(), Box::new(resource))
+            .and_then(|old| old.downcast::<R>().ok())
+            .map(|old| *old)
+    }
+
+    pub fn remove_resource<R: Any + Send + Sync>(&mut self) -> Option<R> {
+        self.resources
+            .remove(&TypeId::of::<R>())
+            .and_then(|old| old.downcast::<R>().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get_resource<R: Any + Send + Sync>(&self) -> Option<&R> {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_ref::<R>())
+    }
+
+    pub fn get_resource_mut<R: Any + Send + Sync>(&mut self) -> Option<&mut R> {
+        self.resources
+            .get_mut(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_mut::<R>())
+    }
+
+    pub fn has_resource<R: Any + Send + Sync>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+
+    // Registers an `Events<E>` resource that is updated at the start of every frame.
+    pub fn add_event<E: Clone + Send + Sync + 'static>(&mut self) {
+        if !self.has_resource::<Events<E>>() {
+            self.insert_resource(Events::<E>::new());
+        }
+        self.event_updaters.insert(TypeId::of::<E>(), event::update_events::<E>);
+    }
+
+    pub fn send_event<E: Clone + Send + Sync + 'static>(&mut self, event: E) {
+        match self.get_resource_mut::<Events<E>>() {
+            Some(events) => events.send(event),
+            None => panic!("Event type {} is not registered", std::any::type_name::<E>()),
+        }
+    }
+
+    pub fn update_events(&mut self) {
+        let updaters: Vec<fn(&mut World)> = self.event_updaters.values().copied().collect();
+        for update in updaters {
+            update(self);
+        }
+    }
+
+    // Deferred scheduler changes; safe to use from inside a running system.
+    pub fn system_commands(&mut self) -> &mut SystemCommands {
+        &mut self.system_commands
+    }
+
     pub fn clear(&mut self) {
+        for entity_id in self.entities.keys() {
+            self.component_store.record_despawned(*entity_id);
+        }
         self.entities.clear();
         self.component_store.clear();
         self.query_cache.clear();
     }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn fixed_time(&self) -> f64 {
+        self.fixed_time
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    // Used when restoring a snapshot; the fixed-step clock restarts from `time`.
+    pub(crate) fn set_clock(&mut self, time: f64, frame: u64) {
+        self.time = time;
+        self.fixed_time = time;
+        self.accumulator = 0.0;
+        self.frame = frame;
+    }
+
+    pub fn interpolation_alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn fixed_timestep(&self) -> f64 {
+        self.fixed_timestep
+    }
+
+    pub fn set_fixed_timestep(&mut self, timestep: f64) {
+        assert!(timestep > 0.0, "Fixed timestep must be positive");
+        self.fixed_timestep = timestep;
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, scale: f64) {
+        self.time_scale = scale.max(0.0);
+    }
+
+    // Real frame deltas above this are clamped, so a long stall cannot
+    // queue up an unbounded number of fixed steps.
+    pub fn set_max_frame_time(&mut self, max_frame_time: f64) {
+        self.max_frame_time = max_frame_time.max(0.0);
+    }
+
+    pub fn set_max_fixed_steps(&mut self, max_steps: u32) {
+        self.max_fixed_steps = max_steps.max(1);
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.step_requested = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // While paused, advance the next frame by exactly one fixed step.
+    pub fn step_once(&mut self) {
+        self.step_requested = true;
+    }
+
+    // Advances the clocks by one frame of real time and returns how the frame
+    // should be simulated. Does not run any systems; see `run_frame`.
+    pub fn step(&mut self, real_dt: f64) -> FrameTime {
+        self.frame += 1;
+
+        let (delta_time, fixed_steps) = if self.paused {
+            if self.step_requested {
+                self.step_requested = false;
+                self.fixed_time += self.fixed_timestep;
+                (self.fixed_timestep, 1)
+            } else {
+                (0.0, 0)
+            }
+        } else {
+            let delta_time = real_dt.clamp(0.0, self.max_frame_time) * self.time_scale;
+            self.accumulator += delta_time;
+
+            let mut steps = 0;
+            while self.accumulator >= self.fixed_timestep && steps < self.max_fixed_steps {
+                self.accumulator -= self.fixed_timestep;
+                self.fixed_time += self.fixed_timestep;
+                steps += 1;
+            }
+            if self.accumulator >= self.fixed_timestep {
+                // Spiral of death: drop the backlog instead of falling further behind.
+                self.accumulator %= self.fixed_timestep;
+            }
+            (delta_time, steps)
+        };
+
+        self.time += delta_time;
+        self.alpha = if self.paused { self.alpha } else { self.accumulator / self.fixed_timestep };
+
+        FrameTime {
+            frame: self.frame,
+            delta_time,
+            time: self.time,
+            fixed_delta: self.fixed_timestep,
+            fixed_steps,
+            fixed_time: self.fixed_time,
+            alpha: self.alpha,
+        }
+    }
+
+    // Steps the clocks and drives the scheduler: pending state transitions
+    // first, then FixedUpdate zero or more times, then Update and LateUpdate
+    // once. Custom phases anchored to these run alongside them.
+    pub fn run_frame(&mut self, scheduler: &mut SystemScheduler, real_dt: f64) -> Result<FrameTime, TX2Error> {
+        let frame = self.step(real_dt);
+        self.update_events();
+        self.running = true;
+        let result = self.run_phases(scheduler, &frame);
+        self.running = false;
+        result.map(|_| frame)
+    }
+
+    fn run_phases(&mut self, scheduler: &mut SystemScheduler, frame: &FrameTime) -> Result<(), TX2Error> {
+        scheduler.apply_state_transitions(self, frame.delta_time, frame.time)?;
+        for step in 0..frame.fixed_steps {
+            let remaining = (frame.fixed_steps - step - 1) as f64;
+            let fixed_time = frame.fixed_time - remaining * frame.fixed_delta;
+            scheduler.execute_phase_group(SystemPhase::FixedUpdate, self, frame.fixed_delta, fixed_time)?;
+        }
+        scheduler.execute_phase_group(SystemPhase::Update, self, frame.delta_time, frame.time)?;
+        scheduler.execute_phase_group(SystemPhase::LateUpdate, self, frame.delta_time, frame.time)
+    }
 }